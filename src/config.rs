@@ -2,21 +2,56 @@ use directories::ProjectDirs;
 use serde::{Deserialize, Serialize};
 use std::fs::File;
 use std::io::Write;
+use std::path::PathBuf;
+use toml::{Table, Value};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Config {
     pub plugin_dir: String,
+    #[serde(default = "default_runs_dir")]
+    pub runs_dir: String,
+}
+
+/// Every setting that can be changed with `xt config`, with a short description of it.
+pub const KEYS: [(&str, &str); 2] = [
+    ("plugin_dir", "Directory plugins are installed into"),
+    (
+        "runs_dir",
+        "Directory the metadata and logs of every run are kept in",
+    ),
+];
+
+/// The directory XTomate keeps its data (plugins, trash, ...) in by default.
+pub fn data_dir() -> PathBuf {
+    ProjectDirs::from("me", "vyPal", "XTomate")
+        .unwrap()
+        .data_dir()
+        .to_path_buf()
+}
+
+/// The directory XTomate keeps its configuration (and user-defined starter workflows) in.
+pub fn config_dir() -> PathBuf {
+    ProjectDirs::from("me", "vyPal", "XTomate")
+        .unwrap()
+        .config_dir()
+        .to_path_buf()
+}
+
+/// The path of the configuration file.
+pub fn config_path() -> PathBuf {
+    config_dir().join("config.toml")
+}
+
+/// Runs are kept in the data directory, next to the plugins.
+fn default_runs_dir() -> String {
+    data_dir().join("runs").to_str().unwrap().to_string()
 }
 
 impl Config {
     pub fn default() -> Self {
         Config {
-            plugin_dir: ProjectDirs::from("me", "vyPal", "XTomate")
-                .unwrap()
-                .data_dir()
-                .to_str()
-                .unwrap()
-                .to_string(),
+            plugin_dir: data_dir().to_str().unwrap().to_string(),
+            runs_dir: default_runs_dir(),
         }
     }
 
@@ -24,21 +59,19 @@ impl Config {
         &self.plugin_dir
     }
 
+    pub fn get_runs_dir(&self) -> &str {
+        &self.runs_dir
+    }
+
     pub fn load() -> Result<Self, Box<dyn std::error::Error>> {
-        let config_path = ProjectDirs::from("me", "vyPal", "XTomate")
-            .unwrap()
-            .config_dir()
-            .join("config.toml");
+        let config_path = config_path();
         let config = std::fs::read_to_string(config_path)?;
         let config: Config = toml::from_str(&config)?;
         Ok(config)
     }
 
     pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let config_path = ProjectDirs::from("me", "vyPal", "XTomate")
-            .unwrap()
-            .config_dir()
-            .to_path_buf();
+        let config_path = config_dir();
         let toml_string = toml::to_string(self)?;
         let _ = std::fs::create_dir_all(config_path.clone());
         let mut file = File::create(config_path.join("config.toml"))?;
@@ -46,6 +79,60 @@ impl Config {
         Ok(())
     }
 
+    /// The value of a setting, or `None` if it isn't set.
+    pub fn get(&self, key: &str) -> Result<Option<Value>, Box<dyn std::error::Error>> {
+        check_key(key)?;
+        Ok(self.to_table()?.get(key).cloned())
+    }
+
+    /// Changes a setting. The value is parsed as TOML (so numbers, booleans and arrays can be
+    /// given), falling back to a plain string.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), Box<dyn std::error::Error>> {
+        check_key(key)?;
+        let mut candidates = vec![];
+        if let Ok(mut parsed) = toml::from_str::<Table>(&format!("value = {}", value)) {
+            candidates.push(parsed.remove("value").unwrap());
+        }
+        candidates.push(Value::String(value.to_string()));
+
+        let mut table = self.to_table()?;
+        for candidate in candidates {
+            table.insert(key.to_string(), candidate);
+            if let Ok(config) = Value::Table(table.clone()).try_into::<Config>() {
+                config.validate()?;
+                *self = config;
+                return Ok(());
+            }
+        }
+        Err(format!("Invalid value `{}` for `{}`", value, key).into())
+    }
+
+    /// Resets a setting to its default value.
+    pub fn unset(&mut self, key: &str) -> Result<(), Box<dyn std::error::Error>> {
+        check_key(key)?;
+        let mut table = self.to_table()?;
+        match Config::default().to_table()?.remove(key) {
+            Some(default) => table.insert(key.to_string(), default),
+            None => table.remove(key),
+        };
+        *self = Value::Table(table).try_into()?;
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.plugin_dir.trim().is_empty() {
+            return Err("plugin_dir must not be empty".to_string());
+        }
+        if self.runs_dir.trim().is_empty() {
+            return Err("runs_dir must not be empty".to_string());
+        }
+        Ok(())
+    }
+
+    fn to_table(&self) -> Result<Table, toml::ser::Error> {
+        Table::try_from(self)
+    }
+
     pub fn load_or_default(save: bool) -> Result<Self, Box<dyn std::error::Error>> {
         match Config::load() {
             Ok(config) => Ok(config),
@@ -60,6 +147,18 @@ impl Config {
     }
 }
 
+fn check_key(key: &str) -> Result<(), String> {
+    if KEYS.iter().any(|(k, _)| *k == key) {
+        Ok(())
+    } else {
+        Err(format!(
+            "Unknown setting `{}`, available settings: {}",
+            key,
+            KEYS.iter().map(|(k, _)| *k).collect::<Vec<_>>().join(", ")
+        ))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -77,6 +176,24 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_runs_dir() {
+        let mut config = Config::default();
+        assert_eq!(
+            PathBuf::from(config.get_runs_dir()),
+            data_dir().join("runs")
+        );
+        config.set("runs_dir", "/var/log/xtomate").unwrap();
+        assert_eq!(config.get_runs_dir(), "/var/log/xtomate");
+        assert!(config.set("runs_dir", "").is_err());
+        config.unset("runs_dir").unwrap();
+        assert_eq!(config.get_runs_dir(), default_runs_dir());
+
+        // Config files written before `runs_dir` existed still load
+        let old: Config = toml::from_str("plugin_dir = \"/opt/plugins\"").unwrap();
+        assert_eq!(old.get_runs_dir(), default_runs_dir());
+    }
+
     #[test]
     fn test_load() {
         let config = Config::default();
@@ -120,4 +237,22 @@ mod tests {
         let loaded_config = Config::load().unwrap();
         assert_eq!(config.get_plugin_dir(), loaded_config.get_plugin_dir());
     }
+
+    #[test]
+    fn test_get_set_unset() {
+        let mut config = Config::default();
+        config.set("plugin_dir", "/tmp/plugins").unwrap();
+        assert_eq!(config.get_plugin_dir(), "/tmp/plugins");
+        assert_eq!(
+            config.get("plugin_dir").unwrap(),
+            Some(Value::String("/tmp/plugins".to_string()))
+        );
+
+        assert!(config.set("plugin_dir", "").is_err());
+        assert!(config.set("unknown", "value").is_err());
+        assert!(config.get("unknown").is_err());
+
+        config.unset("plugin_dir").unwrap();
+        assert_eq!(config.get_plugin_dir(), Config::default().get_plugin_dir());
+    }
 }
@@ -0,0 +1,155 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::workflow::result::ExitStatus;
+use crate::workflow::validate::Issue;
+
+pub type Cause = Box<dyn std::error::Error + Send + Sync>;
+
+/// Everything that can go wrong while loading a workflow, installing its plugins and running
+/// its tasks.
+#[derive(Debug)]
+pub enum Error {
+    /// The workflow file could not be read.
+    Read {
+        path: PathBuf,
+        cause: std::io::Error,
+    },
+    /// The workflow file is not valid TOML, or doesn't describe a workflow.
+    Parse {
+        path: PathBuf,
+        cause: toml::de::Error,
+    },
+    /// The workflow can't be run as written.
+    Validation {
+        workflow: String,
+        issues: Vec<Issue>,
+    },
+    /// A plugin could not be fetched, built or installed from `source`.
+    PluginInstall {
+        plugin: String,
+        source: String,
+        cause: Cause,
+    },
+    /// An installed plugin library could not be loaded or initialized.
+    PluginLoad {
+        plugin: String,
+        path: PathBuf,
+        cause: Cause,
+    },
+    /// A task could not be started.
+    Task { task: String, cause: Cause },
+    /// A hook of a task (or of the workflow, when `task` is its name) could not be run.
+    Hook {
+        task: String,
+        hook: String,
+        cause: Cause,
+    },
+}
+
+impl Error {
+    /// The `xt run` exit code for this error.
+    pub fn exit_status(&self) -> ExitStatus {
+        match self {
+            Error::Read { .. } | Error::Parse { .. } | Error::Validation { .. } => {
+                ExitStatus::InvalidWorkflow
+            }
+            Error::PluginInstall { .. } | Error::PluginLoad { .. } => ExitStatus::PluginError,
+            Error::Task { .. } | Error::Hook { .. } => ExitStatus::TaskFailed,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Read { path, cause } => {
+                write!(f, "could not read `{}`: {}", path.display(), cause)
+            }
+            Error::Parse { path, cause } => write!(
+                f,
+                "`{}` is not a valid workflow: {}",
+                path.display(),
+                cause.to_string().trim_end()
+            ),
+            Error::Validation { workflow, issues } => {
+                write!(f, "workflow `{}` is invalid:", workflow)?;
+                for issue in issues.iter() {
+                    write!(f, "\n  {}", issue)?;
+                }
+                Ok(())
+            }
+            Error::PluginInstall {
+                plugin,
+                source,
+                cause,
+            } => write!(
+                f,
+                "failed to install plugin `{}` from `{}`: {}",
+                plugin, source, cause
+            ),
+            Error::PluginLoad {
+                plugin,
+                path,
+                cause,
+            } => write!(
+                f,
+                "failed to load plugin `{}` from `{}`: {}",
+                plugin,
+                path.display(),
+                cause
+            ),
+            Error::Task { task, cause } => write!(f, "task `{}`: {}", task, cause),
+            Error::Hook { task, hook, cause } => {
+                write!(f, "task `{}`: hook `{}`: {}", task, hook, cause)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Read { cause, .. } => Some(cause),
+            Error::Parse { cause, .. } => Some(cause),
+            Error::Validation { .. } => None,
+            Error::PluginInstall { cause, .. }
+            | Error::PluginLoad { cause, .. }
+            | Error::Task { cause, .. }
+            | Error::Hook { cause, .. } => Some(cause.as_ref()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workflow::validate::Scope;
+
+    #[test]
+    fn test_display() {
+        let error = Error::Validation {
+            workflow: "test".to_string(),
+            issues: vec![Issue {
+                scope: Scope::Task("build".to_string()),
+                message: "depends on undefined task `prep`".to_string(),
+            }],
+        };
+        assert_eq!(
+            error.to_string(),
+            "workflow `test` is invalid:\n  task `build`: depends on undefined task `prep`"
+        );
+        assert_eq!(error.exit_status(), ExitStatus::InvalidWorkflow);
+
+        let error = Error::PluginInstall {
+            plugin: "notify".to_string(),
+            source: "./notify".to_string(),
+            cause: "Local path does not exist".into(),
+        };
+        assert_eq!(
+            error.to_string(),
+            "failed to install plugin `notify` from `./notify`: Local path does not exist"
+        );
+        assert_eq!(error.exit_status(), ExitStatus::PluginError);
+    }
+}
@@ -1,15 +1,31 @@
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
+use colored::*;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 use toml::to_string;
 
+use error::Error;
+use plugins::manager::PluginManager;
+use trash::Trash;
+use workflow::duration::format_duration;
+use workflow::graph::TaskGraph;
+use workflow::process::wait_for_signal;
+use workflow::result::ExitStatus;
 use workflow::runner::Runner;
-use workflow::structure::{Dependency, WorkFlow};
+use workflow::runs::{self as runs, RunLog, RunMetadata};
+use workflow::structure::{Dependency, OutputMode, WorkFlow};
+use workflow::validate::{validate, Issue, Scope, STATUSES};
 
 mod config;
+mod error;
 mod plugins;
+mod starters;
+mod trash;
 mod workflow;
 
 #[derive(Parser)]
@@ -25,21 +41,210 @@ enum Commands {
     Create {
         /// The name of the workflow
         name: String,
+
+        /// Start from a template (build-test-deploy, backup, plugin-hook-demo or one from the
+        /// `templates` directory in the config directory)
+        #[arg(short, long)]
+        template: Option<String>,
     },
-    /// Deletes a workflow
+    /// Moves a workflow to the trash
     Delete {
         /// The name of the workflow
         name: String,
 
-        /// Whether to force deletion
+        /// Don't ask for confirmation
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// Restores a workflow from the trash
+    Restore {
+        /// The name of the workflow, or the id of a trash entry
+        name: String,
+
+        /// Overwrite the workflow if it has been recreated since it was deleted
         #[arg(short, long)]
         force: bool,
     },
+    /// Lists the workflows in the trash
+    Trash,
     /// Runs a workflow
+    #[command(after_help = "Exit codes:
+    0  every task succeeded (or was up to date, had a false condition or wasn't needed)
+    1  a task failed or timed out, or was skipped or not run because another task failed
+    2  the workflow could not be read or is invalid
+    3  a plugin could not be installed or loaded
+  130  the run was interrupted with Ctrl-C or SIGTERM")]
     Run {
         /// The name of the workflow
         name: String,
+
+        /// Only run these tasks (and the tasks they depend on)
+        tasks: Vec<String>,
+
+        /// Print the stages and resolved commands without running anything
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Run at most this many tasks at the same time (overrides `max_parallel`)
+        #[arg(short, long, value_parser = clap::value_parser!(u64).range(1..))]
+        jobs: Option<u64>,
+
+        /// Keep starting independent tasks after a task fails (only for workflows with
+        /// `on_failure = "stop"`)
+        ///
+        /// Workflows keep going after a failure by default (`on_failure = "continue"`), so this
+        /// only changes anything for workflows that set `on_failure = "stop"`.
+        #[arg(short, long)]
+        keep_going: bool,
+
+        /// Print each task's output in one block once it finishes, instead of line by line
+        #[arg(long)]
+        group_output: bool,
+
+        /// Run tasks with `sources` even if nothing changed since they last succeeded
+        #[arg(short, long)]
+        force: bool,
+
+        /// Set a workflow parameter (can be given more than once)
+        #[arg(long = "param", value_name = "NAME=VALUE", value_parser = parse_param)]
+        params: Vec<(String, String)>,
+
+        /// Read workflow parameters from a TOML file of `NAME = VALUE` lines
+        #[arg(long, value_name = "FILE")]
+        params_file: Option<PathBuf>,
+    },
+    /// Shows the logs of past runs of a workflow
+    Logs {
+        /// The name of the workflow
+        name: String,
+
+        /// The run to show (`latest` for the most recent one); lists the runs if not given
+        run: Option<String>,
+
+        /// Print the stdout, stderr and retries logs of this task
+        task: Option<String>,
+    },
+    /// Lists past runs of a workflow, or of every workflow
+    History {
+        /// The name of the workflow
+        name: Option<String>,
+
+        /// Only list runs of this task, with its status instead of the run's outcome
+        #[arg(short, long)]
+        task: Option<String>,
+
+        /// Only list runs in which the task finished with this status (as in dependencies)
+        #[arg(short, long, requires = "task")]
+        status: Option<String>,
+
+        /// Only list this many of the most recent runs
+        #[arg(short = 'n', long)]
+        limit: Option<usize>,
+    },
+    /// Shows the details of a past run
+    Show {
+        /// The id of the run (as listed by `xt history`), or `latest`
+        run: String,
+
+        /// The workflow the run belongs to, if runs of several workflows have this id
+        #[arg(short, long)]
+        workflow: Option<String>,
+    },
+    /// Prints the task graph of a workflow
+    Graph {
+        /// The name of the workflow
+        name: String,
+
+        /// The output format
+        #[arg(short, long, value_enum, default_value_t = GraphFormat::Dot)]
+        format: GraphFormat,
+    },
+    /// Checks a workflow for problems without running it
+    Validate {
+        /// The name of the workflow
+        name: String,
     },
+    /// Manages installed plugins
+    Plugin {
+        #[command(subcommand)]
+        command: PluginCommands,
+    },
+    /// Shows and changes settings
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommands {
+    /// Prints the path of the configuration file
+    Path,
+    /// Prints the value of a setting
+    Get {
+        /// The name of the setting
+        key: String,
+    },
+    /// Changes a setting
+    Set {
+        /// The name of the setting
+        key: String,
+
+        /// The new value
+        value: String,
+    },
+    /// Resets a setting to its default value
+    Unset {
+        /// The name of the setting
+        key: String,
+    },
+    /// Lists every setting with its current value
+    List,
+}
+
+#[derive(Subcommand)]
+enum PluginCommands {
+    /// Lists installed plugins
+    List,
+    /// Installs a plugin
+    Install {
+        /// The name of the plugin
+        name: String,
+
+        /// A git URL, a GitHub `owner/repo` shorthand or a local path
+        source: String,
+
+        /// The version requirement the plugin has to satisfy
+        #[arg(long = "version", default_value = "*")]
+        version_req: String,
+    },
+    /// Fetches and rebuilds an installed plugin
+    Update {
+        /// The name of the plugin
+        name: String,
+
+        /// A new version requirement for the plugin
+        #[arg(long = "version")]
+        version_req: Option<String>,
+    },
+    /// Removes an installed plugin
+    Remove {
+        /// The name of the plugin
+        name: String,
+    },
+    /// Shows details about an installed plugin
+    Info {
+        /// The name of the plugin
+        name: String,
+    },
+}
+
+#[derive(Clone, ValueEnum)]
+enum GraphFormat {
+    /// Graphviz DOT
+    Dot,
+    /// Mermaid flowchart
+    Mermaid,
 }
 
 fn write_workflow(workflow: &WorkFlow, file_path: &str) -> Result<(), Box<dyn std::error::Error>> {
@@ -49,50 +254,624 @@ fn write_workflow(workflow: &WorkFlow, file_path: &str) -> Result<(), Box<dyn st
     Ok(())
 }
 
-fn read_workflow(file_path: &mut String) -> Result<WorkFlow, Box<dyn std::error::Error>> {
+fn add_extension(file_path: &mut String) {
     if !file_path.ends_with(".toml") {
         file_path.push_str(".toml");
     }
-    let file = std::fs::read_to_string(file_path)?;
-    let workflow: WorkFlow = toml::from_str(&file)?;
+}
+
+fn read_workflow(file_path: &mut String) -> Result<WorkFlow, Error> {
+    add_extension(file_path);
+    let path = PathBuf::from(&file_path);
+    let file = std::fs::read_to_string(&path).map_err(|cause| Error::Read {
+        path: path.clone(),
+        cause,
+    })?;
+    let mut workflow: WorkFlow =
+        toml::from_str(&file).map_err(|cause| Error::Parse { path, cause })?;
+    workflow
+        .expand_matrices()
+        .map_err(|issues| Error::Validation {
+            workflow: workflow.name.clone(),
+            issues,
+        })?;
     Ok(workflow)
 }
 
+/// The SHA-256 of a workflow file, recorded with every run so runs of different versions of
+/// the workflow can be told apart.
+fn workflow_hash(path: &str) -> Option<String> {
+    let contents = std::fs::read(path).ok()?;
+    Some(format!("{:x}", Sha256::digest(contents)))
+}
+
+fn parse_param(param: &str) -> Result<(String, String), String> {
+    param
+        .split_once('=')
+        .map(|(name, value)| (name.trim().to_string(), value.to_string()))
+        .ok_or_else(|| format!("`{}` is not of the form NAME=VALUE", param))
+}
+
+fn read_params_file(path: &Path, workflow: &str) -> Result<Vec<(String, String)>, Error> {
+    let file = std::fs::read_to_string(path).map_err(|cause| Error::Read {
+        path: path.to_path_buf(),
+        cause,
+    })?;
+    let table: toml::Table = toml::from_str(&file).map_err(|cause| Error::Parse {
+        path: path.to_path_buf(),
+        cause,
+    })?;
+    let mut params = vec![];
+    let mut issues = vec![];
+    for (name, value) in table {
+        match workflow::params::value_to_string(&value) {
+            Some(value) => params.push((name, value)),
+            None => issues.push(Issue {
+                scope: Scope::Param(name),
+                message: format!(
+                    "the value in `{}` must be a string, integer or boolean",
+                    path.display()
+                ),
+            }),
+        }
+    }
+    if !issues.is_empty() {
+        return Err(Error::Validation {
+            workflow: workflow.to_string(),
+            issues,
+        });
+    }
+    Ok(params)
+}
+
+fn exit_with_error(message: impl std::fmt::Display) -> ! {
+    eprintln!("{} {}", "error:".red().bold(), message);
+    std::process::exit(1);
+}
+
+/// Like `exit_with_error`, but with the documented `xt run` exit code for the error.
+fn exit_with_run_error(error: Error) -> ! {
+    eprintln!("{} {}", "error:".red().bold(), error);
+    std::process::exit(error.exit_status().code());
+}
+
+fn confirm(question: &str) -> bool {
+    print!("{} [y/N] ", question);
+    let _ = std::io::stdout().flush();
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+fn prompt(question: &str, default: Option<&str>) -> String {
+    match default {
+        Some(default) => print!("{} [{}]: ", question, default),
+        None => print!("{}: ", question),
+    }
+    let _ = std::io::stdout().flush();
+    let mut answer = String::new();
+    let _ = std::io::stdin().read_line(&mut answer);
+    match answer.trim() {
+        "" => default.unwrap_or_default().to_string(),
+        answer => answer.to_string(),
+    }
+}
+
+fn load_plugin_manager() -> PluginManager {
+    let config = config::Config::load_or_default(true).unwrap_or_else(|e| exit_with_error(e));
+    PluginManager::load_or_default(PathBuf::from(config.get_plugin_dir()), true)
+        .unwrap_or_else(|e| exit_with_error(e))
+}
+
+/// Stops the run gracefully on the first Ctrl-C or SIGTERM, and exits immediately on the second.
+async fn handle_signals(runner: Arc<Runner>) {
+    let signal = wait_for_signal().await;
+    eprintln!(
+        "{} stopping running tasks, press Ctrl-C again to exit immediately",
+        "Interrupted:".yellow().bold()
+    );
+    runner.interrupt(signal);
+
+    wait_for_signal().await;
+    runner.kill();
+    std::process::exit(ExitStatus::Interrupted.code());
+}
+
+fn runs_dir() -> PathBuf {
+    let config = config::Config::load_or_default(false).unwrap_or_else(|e| exit_with_error(e));
+    PathBuf::from(config.get_runs_dir())
+}
+
+fn format_timestamp(timestamp: u64) -> String {
+    chrono::DateTime::from_timestamp(timestamp as i64, 0)
+        .unwrap_or_default()
+        .with_timezone(&chrono::Local)
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
+/// How a recorded run ended, coloured like the run summary.
+fn run_outcome(run: &RunMetadata) -> ColoredString {
+    match run.exit_code {
+        None => "unfinished".yellow(),
+        Some(0) => "succeeded".green(),
+        Some(code) if run.interrupted => format!("interrupted (exit code {})", code).yellow(),
+        Some(code) => format!("failed (exit code {})", code).red(),
+    }
+}
+
+/// One line about a recorded run, as listed by `xt logs` and `xt history`: with its
+/// workflow if `with_workflow`, and with the status of `task` instead of the run's outcome if
+/// given.
+fn print_run_line(run: &RunMetadata, with_workflow: bool, task: Option<&str>) {
+    print!("{}", run.id.bold());
+    if with_workflow {
+        print!("  {}", run.workflow);
+    }
+    print!("  started {}", format_timestamp(run.started_at));
+    if let Some(finished_at) = run.finished_at {
+        print!(
+            "  took {}",
+            format_duration(Duration::from_secs(
+                finished_at.saturating_sub(run.started_at)
+            ))
+        );
+    }
+    match task.and_then(|task| run.tasks.iter().find(|t| t.name == task)) {
+        Some(task) => println!("  {} {}", task.name, task.status),
+        None => println!("  {}", run_outcome(run)),
+    }
+}
+
+/// The details of a recorded run, as shown by `xt show` and `xt logs WORKFLOW RUN`.
+fn print_run(run: &RunMetadata, dir: &Path) {
+    println!(
+        "Run {} of `{}`: {}",
+        run.id.bold(),
+        run.workflow,
+        run_outcome(run)
+    );
+    println!("  started   {}", format_timestamp(run.started_at));
+    if let Some(finished_at) = run.finished_at {
+        println!("  finished  {}", format_timestamp(finished_at));
+    }
+    if let Some(hash) = &run.workflow_hash {
+        println!("  workflow  sha256 {}", &hash[..hash.len().min(12)]);
+    }
+    let width = run.tasks.iter().map(|t| t.name.len()).max().unwrap_or(0);
+    for task in run.tasks.iter() {
+        print!("  {:width$}  {}", task.name, task.status, width = width);
+        let mut details = vec![];
+        if let Some(duration_ms) = task.duration_ms {
+            details.push(format_duration(Duration::from_millis(duration_ms)));
+        }
+        if let Some(exit_code) = task.exit_code {
+            details.push(format!("exit code {}", exit_code));
+        }
+        if task.attempts.len() > 1 {
+            details.push(format!("after {} attempts", task.attempts.len()));
+        }
+        if !details.is_empty() {
+            print!(" ({})", details.join(", "));
+        }
+        println!();
+    }
+    println!(
+        "Logs in {} (xt logs {} {})",
+        dir.display(),
+        run.workflow,
+        run.id
+    );
+}
+
+fn load_trash() -> Trash {
+    Trash::load_or_default(config::data_dir().join("trash")).unwrap_or_else(|e| exit_with_error(e))
+}
+
 #[tokio::main]
 async fn main() {
     let cli = Cli::parse();
 
     match &cli.command {
-        Some(Commands::Create { name }) => {
+        Some(Commands::Create { name, template }) => {
             let xtomate_version = env!("CARGO_PKG_VERSION");
-            let mut workflow = WorkFlow::new(name.to_string(), xtomate_version.to_string(), None);
-            workflow.add_task("task1".to_string(), "echo Hello".to_string(), None);
-            workflow.add_task(
-                "task2".to_string(),
-                "echo World".to_string(),
-                Some(vec![Dependency::Simple("task1".to_string())]),
-            );
-            write_workflow(&workflow, &format!("{}.toml", name)).unwrap();
+            if let Some(kind) = template {
+                let source = starters::load(&config::config_dir().join("templates"), kind)
+                    .unwrap_or_else(|e| exit_with_error(e));
+
+                let mut values = HashMap::new();
+                values.insert("name".to_string(), name.to_string());
+                values.insert("version".to_string(), xtomate_version.to_string());
+                for placeholder in starters::placeholders(&source) {
+                    values.entry(placeholder.key.clone()).or_insert_with(|| {
+                        prompt(&placeholder.key, placeholder.default.as_deref())
+                    });
+                }
+
+                let rendered = starters::render(&source, &values);
+                if let Err(e) = toml::from_str::<WorkFlow>(&rendered) {
+                    exit_with_error(format!(
+                        "template `{}` does not produce a valid workflow: {}",
+                        kind, e
+                    ));
+                }
+                std::fs::write(format!("{}.toml", name), rendered)
+                    .unwrap_or_else(|e| exit_with_error(e));
+            } else {
+                let mut workflow =
+                    WorkFlow::new(name.to_string(), xtomate_version.to_string(), None);
+                workflow.add_task("task1".to_string(), "echo Hello".to_string(), None);
+                workflow.add_task(
+                    "task2".to_string(),
+                    "echo World".to_string(),
+                    Some(vec![Dependency::Simple("task1".to_string())]),
+                );
+                write_workflow(&workflow, &format!("{}.toml", name)).unwrap();
+            }
             println!("Creating workflow: {}", name);
         }
         Some(Commands::Delete { name, force }) => {
-            if *force {
-                println!("Force deleting workflow: {}", name);
-            } else {
-                println!("Deleting workflow: {}", name);
+            let mut file_path = name.clone();
+            add_extension(&mut file_path);
+            let path = PathBuf::from(&file_path);
+            if !path.is_file() {
+                exit_with_error(format!("Workflow file `{}` does not exist", file_path));
+            }
+            if !*force && !confirm(&format!("Move workflow `{}` to the trash?", file_path)) {
+                println!("Aborted");
+                return;
             }
+
+            let mut trash = load_trash();
+            let entry = trash.trash(&path).unwrap_or_else(|e| exit_with_error(e));
+            println!(
+                "{}",
+                format!(
+                    "Moved workflow `{}` to the trash, restore it with `xt restore {}`",
+                    file_path, entry.name
+                )
+                .green()
+            );
         }
-        Some(Commands::Run { name }) => {
-            let config = config::Config::load_or_default(true).unwrap();
-            let plugin_manager = plugins::manager::PluginManager::load_or_default(
-                PathBuf::from(config.get_plugin_dir()),
-                true,
-            )
-            .unwrap();
-            let workflow = read_workflow(&mut name.clone()).unwrap();
+        Some(Commands::Restore { name, force }) => {
+            let mut trash = load_trash();
+            let path = trash
+                .restore(name, *force)
+                .unwrap_or_else(|e| exit_with_error(e));
+            println!(
+                "{}",
+                format!("Restored workflow to `{}`", path.display()).green()
+            );
+        }
+        Some(Commands::Trash) => {
+            let trash = load_trash();
+            if trash.entries.is_empty() {
+                println!("The trash is empty");
+            }
+            for entry in trash.entries.iter() {
+                println!(
+                    "{}  {}  deleted {}  from {}",
+                    entry.id.bold(),
+                    entry.name,
+                    format_timestamp(entry.deleted_at),
+                    entry.original_path.display()
+                );
+            }
+        }
+        Some(Commands::Run {
+            name,
+            tasks,
+            dry_run,
+            jobs,
+            keep_going,
+            group_output,
+            force,
+            params,
+            params_file,
+        }) => {
+            let plugin_manager = load_plugin_manager();
+            let mut file = name.clone();
+            let workflow = read_workflow(&mut file).unwrap_or_else(|e| exit_with_run_error(e));
+            let mut issues = validate(&workflow);
+            // A matrix task stands for all of its instances
+            let tasks: Vec<String> = tasks
+                .iter()
+                .flat_map(|task| {
+                    workflow
+                        .matrix_instances(task)
+                        .cloned()
+                        .unwrap_or_else(|| vec![task.clone()])
+                })
+                .collect();
+            for task in tasks.iter() {
+                if workflow.get_task(task).is_none() {
+                    issues.push(Issue {
+                        scope: Scope::Workflow,
+                        message: format!("no task named `{}`", task),
+                    });
+                }
+            }
+            if !issues.is_empty() {
+                exit_with_run_error(Error::Validation {
+                    workflow: workflow.name.clone(),
+                    issues,
+                });
+            }
+            let workflow_name = workflow.name.clone();
+            let mut param_values = match params_file {
+                Some(path) => read_params_file(path, &workflow.name)
+                    .unwrap_or_else(|e| exit_with_run_error(e)),
+                None => vec![],
+            };
+            param_values.extend(params.iter().cloned());
             let mut runner = Runner::new(workflow, plugin_manager);
-            runner.load();
-            Arc::new(runner).run_all().await;
+            runner.set_params(param_values);
+            if let Some(dir) = PathBuf::from(&file).parent() {
+                runner.set_base_dir(dir.to_path_buf());
+            }
+            runner.set_targets(tasks.clone());
+            if let Some(jobs) = jobs {
+                runner.set_jobs(*jobs as usize);
+            }
+            runner.set_keep_going(*keep_going);
+            runner.set_force(*force);
+            runner.set_fingerprints_dir(config::data_dir().join("fingerprints"));
+            if *group_output {
+                runner.set_output(OutputMode::Grouped);
+            }
+            runner.plan().unwrap_or_else(|e| exit_with_run_error(e));
+            if *dry_run {
+                print!("{}", runner.describe_plan());
+                return;
+            }
+            runner
+                .load_plugins()
+                .unwrap_or_else(|e| exit_with_run_error(e));
+            match RunLog::create(&runs_dir(), &workflow_name, workflow_hash(&file)) {
+                Ok(log) => runner.set_log(log),
+                Err(e) => eprintln!(
+                    "{} could not create a log directory for this run: {}",
+                    "warning:".yellow().bold(),
+                    e
+                ),
+            }
+            let runner = Arc::new(runner);
+            tokio::spawn(handle_signals(Arc::clone(&runner)));
+            let result = Arc::clone(&runner).run_all().await;
+            println!("{}", result);
+            if let Some(log) = runner.log() {
+                println!(
+                    "Logs in {} (xt logs {} {})",
+                    log.dir().display(),
+                    workflow_name,
+                    log.id()
+                );
+            }
+            std::process::exit(result.exit_status().code());
+        }
+        Some(Commands::Logs { name, run, task }) => {
+            let Some(run) = run else {
+                let runs =
+                    runs::list_runs(&runs_dir(), Some(name)).unwrap_or_else(|e| exit_with_error(e));
+                for run in runs.iter() {
+                    print_run_line(run, false, None);
+                }
+                return;
+            };
+            let (run, dir) =
+                runs::find_run(&runs_dir(), Some(name), run).unwrap_or_else(|e| exit_with_error(e));
+            match task {
+                Some(task) => {
+                    let mut found = false;
+                    for kind in ["stdout", "stderr", "retries"] {
+                        if let Ok(log) = std::fs::read_to_string(runs::log_path(&dir, task, kind)) {
+                            found = true;
+                            println!("{}", format!("==> {} {} <==", task, kind).bold());
+                            print!("{}", log);
+                        }
+                    }
+                    if !found {
+                        exit_with_error(format!("Task `{}` has no logs in run `{}`", task, run.id));
+                    }
+                }
+                None => print_run(&run, &dir),
+            }
+        }
+        Some(Commands::History {
+            name,
+            task,
+            status,
+            limit,
+        }) => {
+            if let Some(status) = status {
+                if !STATUSES.contains(&status.as_str()) {
+                    exit_with_error(format!(
+                        "Unknown status `{}` (expected one of: {})",
+                        status,
+                        STATUSES.join(", ")
+                    ));
+                }
+            }
+            let mut runs = runs::list_runs(&runs_dir(), name.as_deref())
+                .unwrap_or_else(|e| exit_with_error(e));
+            if let Some(task) = task {
+                runs.retain(|run| {
+                    run.tasks.iter().any(|t| {
+                        &t.name == task
+                            && status
+                                .as_ref()
+                                .is_none_or(|status| t.status.satisfies(status))
+                    })
+                });
+            }
+            if let Some(limit) = limit {
+                runs.drain(..runs.len().saturating_sub(*limit));
+            }
+            if runs.is_empty() {
+                println!("No matching runs");
+            }
+            for run in runs.iter() {
+                print_run_line(run, name.is_none(), task.as_deref());
+            }
+        }
+        Some(Commands::Show { run, workflow }) => {
+            let (run, dir) = runs::find_run(&runs_dir(), workflow.as_deref(), run)
+                .unwrap_or_else(|e| exit_with_error(e));
+            print_run(&run, &dir);
+        }
+        Some(Commands::Graph { name, format }) => {
+            let workflow = read_workflow(&mut name.clone()).unwrap_or_else(|e| exit_with_error(e));
+            let graph = TaskGraph::new(&workflow);
+            match format {
+                GraphFormat::Dot => print!("{}", graph.to_dot()),
+                GraphFormat::Mermaid => print!("{}", graph.to_mermaid()),
+            }
+        }
+        Some(Commands::Validate { name }) => {
+            let workflow = read_workflow(&mut name.clone()).unwrap_or_else(|e| exit_with_error(e));
+            let issues = validate(&workflow);
+            if issues.is_empty() {
+                println!(
+                    "{}",
+                    format!("Workflow `{}` is valid", workflow.name).green()
+                );
+            } else {
+                for issue in issues.iter() {
+                    eprintln!("{} {}", "error:".red().bold(), issue);
+                }
+                eprintln!(
+                    "{}",
+                    format!(
+                        "Found {} problem(s) in workflow `{}`",
+                        issues.len(),
+                        workflow.name
+                    )
+                    .red()
+                );
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Plugin { command }) => {
+            let mut plugin_manager = load_plugin_manager();
+            match command {
+                PluginCommands::List => {
+                    if plugin_manager.plugins.is_empty() {
+                        println!("No plugins installed");
+                    }
+                    for plugin in plugin_manager.plugins.iter() {
+                        let library_path = plugin_manager.library_path(&plugin.name);
+                        println!(
+                            "{}  {}  {}  {}",
+                            plugin.name.bold(),
+                            plugin.source,
+                            plugin.version.as_deref().unwrap_or("*"),
+                            if library_path.exists() {
+                                library_path.display().to_string()
+                            } else {
+                                "(not installed)".to_string()
+                            }
+                        );
+                    }
+                }
+                PluginCommands::Install {
+                    name,
+                    source,
+                    version_req,
+                } => {
+                    plugin_manager
+                        .add_and_install(name.clone(), source.clone(), version_req.clone())
+                        .unwrap_or_else(|e| exit_with_error(e));
+                    println!("{}", format!("Installed plugin `{}`", name).green());
+                }
+                PluginCommands::Update { name, version_req } => {
+                    let plugin = plugin_manager.get_plugin(name).unwrap_or_else(|| {
+                        exit_with_error(format!("Plugin `{}` is not installed", name))
+                    });
+                    let version_req = version_req
+                        .clone()
+                        .or(plugin.version.clone())
+                        .unwrap_or("*".to_string());
+                    plugin_manager
+                        .add_and_install(name.clone(), plugin.source.clone(), version_req)
+                        .unwrap_or_else(|e| exit_with_error(e));
+                    println!("{}", format!("Updated plugin `{}`", name).green());
+                }
+                PluginCommands::Remove { name } => {
+                    plugin_manager
+                        .remove_plugin(name)
+                        .unwrap_or_else(|e| exit_with_error(e));
+                    println!("{}", format!("Removed plugin `{}`", name).green());
+                }
+                PluginCommands::Info { name } => {
+                    let plugin = plugin_manager.get_plugin(name).unwrap_or_else(|| {
+                        exit_with_error(format!("Plugin `{}` is not installed", name))
+                    });
+                    let library_path = plugin_manager.library_path(name);
+                    println!("{}", plugin.name.bold());
+                    println!("  source:       {}", plugin.source);
+                    println!(
+                        "  requirement:  {}",
+                        plugin.version.as_deref().unwrap_or("*")
+                    );
+                    match plugin_manager.manifest(name) {
+                        Ok(manifest) => {
+                            println!("  version:      {}", manifest.version);
+                            println!("  requires:     XTomate {}", manifest.xtomate_version);
+                            println!("  build:        {}", manifest.build);
+                        }
+                        Err(_) => println!("  version:      (manifest not found)"),
+                    }
+                    println!(
+                        "  library:      {}{}",
+                        library_path.display(),
+                        if library_path.exists() {
+                            ""
+                        } else {
+                            " (not installed)"
+                        }
+                    );
+                }
+            }
+        }
+        Some(Commands::Config { command }) => {
+            let mut config =
+                config::Config::load_or_default(false).unwrap_or_else(|e| exit_with_error(e));
+            match command {
+                ConfigCommands::Path => println!("{}", config::config_path().display()),
+                ConfigCommands::Get { key } => {
+                    match config.get(key).unwrap_or_else(|e| exit_with_error(e)) {
+                        Some(toml::Value::String(value)) => println!("{}", value),
+                        Some(value) => println!("{}", value),
+                        None => {}
+                    }
+                }
+                ConfigCommands::Set { key, value } => {
+                    config
+                        .set(key, value)
+                        .unwrap_or_else(|e| exit_with_error(e));
+                    config.save().unwrap_or_else(|e| exit_with_error(e));
+                }
+                ConfigCommands::Unset { key } => {
+                    config.unset(key).unwrap_or_else(|e| exit_with_error(e));
+                    config.save().unwrap_or_else(|e| exit_with_error(e));
+                }
+                ConfigCommands::List => {
+                    for (key, description) in config::KEYS.iter() {
+                        let value = match config.get(key).unwrap_or_else(|e| exit_with_error(e)) {
+                            Some(value) => value.to_string(),
+                            None => "(not set)".to_string(),
+                        };
+                        println!(
+                            "{} = {}  {}",
+                            key.bold(),
+                            value,
+                            format!("# {}", description).dimmed()
+                        );
+                    }
+                }
+            }
         }
         None => {
             println!("No command provided");
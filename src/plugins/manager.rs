@@ -12,6 +12,8 @@ use semver::{Version, VersionReq};
 use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
 
+use crate::error::{Cause, Error};
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct PluginManager {
     pub plugins: Vec<Plugin>,
@@ -87,15 +89,32 @@ impl PluginManager {
         Ok(())
     }
 
-    pub fn install_plugin(&mut self, name: String) -> Result<(), Box<dyn std::error::Error>> {
-        let install_path = self.plugin_dir.join("installed").join(&name);
+    /// Fetches, builds and installs a registered plugin.
+    pub fn install_plugin(&mut self, name: String) -> Result<(), Error> {
+        self.fetch_and_build(&name)
+            .map_err(|cause| Error::PluginInstall {
+                source: self
+                    .get_plugin(&name)
+                    .map(|p| p.source.clone())
+                    .unwrap_or_default(),
+                plugin: name,
+                cause,
+            })
+    }
+
+    fn fetch_and_build(&mut self, name: &str) -> Result<(), Cause> {
+        let install_path = self.plugin_dir.join("installed").join(name);
         fs::create_dir_all(&install_path)?;
-        let build_path = self.plugin_dir.join("build").join(&name);
+        let build_path = self.plugin_dir.join("build").join(name);
         fs::create_dir_all(&build_path)?;
-        let plugin = self.get_plugin_mut(&name).unwrap();
+        let library_path = self.library_path(name);
+        let plugin = self
+            .get_plugin_mut(name)
+            .ok_or_else(|| format!("Plugin `{}` is not registered", name))?;
         plugin.set_build_path(build_path.clone());
 
-        if is_git_url(&mut plugin.source.to_string()) {
+        let mut source = plugin.source.clone();
+        if is_git_url(&mut source) {
             let state = RefCell::new(State {
                 progress: None,
                 total: 0,
@@ -107,7 +126,7 @@ impl PluginManager {
             cb.transfer_progress(|stats| {
                 let mut state = state.borrow_mut();
                 state.progress = Some(stats.to_owned());
-                print(&mut *state);
+                print(&mut state);
                 true
             });
 
@@ -117,14 +136,14 @@ impl PluginManager {
                 state.path = path.map(|p| p.to_path_buf());
                 state.current = cur;
                 state.total = total;
-                print(&mut *state);
+                print(&mut state);
             });
 
             let mut fo = FetchOptions::new();
             fo.remote_callbacks(cb);
 
             if let Ok(repo) = Repository::open(build_path.clone()) {
-                let mut remote = repo.remote_anonymous(&plugin.source)?;
+                let mut remote = repo.remote_anonymous(&source)?;
                 remote.fetch(&["refs/heads/*:refs/remotes/origin/*"], Some(&mut fo), None)?;
                 let default_branch = remote.default_branch()?;
                 let branch_refspec = default_branch.as_str().unwrap_or("refs/heads/main");
@@ -136,35 +155,49 @@ impl PluginManager {
                 RepoBuilder::new()
                     .fetch_options(fo)
                     .with_checkout(co)
-                    .clone(&plugin.source, &build_path)?;
+                    .clone(&source, &build_path)?;
             }
         } else {
             let local_path = Path::new(&plugin.source);
             if !local_path.exists() {
-                return Err("Local path does not exist".into());
+                return Err(format!("Local path `{}` does not exist", plugin.source).into());
             }
 
-            fs::copy(local_path, &build_path)?;
+            copy_dir(local_path, &build_path)?;
         }
 
         let manifest_path = build_path.join("plugin.toml");
-        let manifest = fs::read_to_string(&manifest_path)?;
-        let manifest: PluginManifest = toml::from_str(&manifest)?;
+        let manifest = fs::read_to_string(&manifest_path)
+            .map_err(|e| format!("Could not read `{}`: {}", manifest_path.display(), e))?;
+        let manifest: PluginManifest = toml::from_str(&manifest)
+            .map_err(|e| format!("Invalid manifest `{}`: {}", manifest_path.display(), e))?;
 
         if manifest.name != name {
-            return Err("Plugin name does not match manifest name".into());
+            return Err(format!(
+                "Plugin name does not match manifest name `{}`",
+                manifest.name
+            )
+            .into());
         }
 
-        let plugin_version_req = VersionReq::parse(&plugin.version.as_ref().unwrap())?;
+        let plugin_version_req = VersionReq::parse(plugin.version.as_deref().unwrap_or("*"))?;
         let plugin_version = Version::parse(&manifest.version)?;
         if !plugin_version_req.matches(&plugin_version) {
-            return Err("Plugin version does not match manifest version".into());
+            return Err(format!(
+                "Plugin version {} does not match the required version {}",
+                manifest.version, plugin_version_req
+            )
+            .into());
         }
 
         let plugin_xtomate_version_req = VersionReq::parse(&manifest.xtomate_version)?;
         let xtomate_version = Version::parse(env!("CARGO_PKG_VERSION"))?;
         if !plugin_xtomate_version_req.matches(&xtomate_version) {
-            return Err("XTomate version does not match manifest version".into());
+            return Err(format!(
+                "Plugin requires XTomate {}, but this is version {}",
+                manifest.xtomate_version, xtomate_version
+            )
+            .into());
         }
 
         println!("{}", format!("Building plugin: {}", name).green());
@@ -177,7 +210,7 @@ impl PluginManager {
             .output()?;
 
         if !build_command.status.success() {
-            return Err("Failed to build plugin".into());
+            return Err(format!("Build command `{}` failed", manifest.build).into());
         }
 
         let output_path = build_path.join(&manifest.output_dir).join(format!(
@@ -186,16 +219,68 @@ impl PluginManager {
             name,
             consts::DLL_SUFFIX
         ));
-        let install_path = install_path.join(format!(
+        plugin.set_install_path(library_path.clone());
+        fs::create_dir_all(&install_path)?;
+        fs::copy(&output_path, library_path)
+            .map_err(|e| format!("Could not copy `{}`: {}", output_path.display(), e))?;
+
+        Ok(())
+    }
+
+    /// Where the built library of a plugin is installed to.
+    pub fn library_path(&self, name: &str) -> PathBuf {
+        self.plugin_dir.join("installed").join(name).join(format!(
             "{}{}{}",
             consts::DLL_PREFIX,
             name,
             consts::DLL_SUFFIX
-        ));
-        plugin.set_install_path(install_path.clone());
-        fs::create_dir_all(install_path.parent().unwrap())?;
-        fs::copy(output_path, install_path)?;
+        ))
+    }
+
+    /// Reads the manifest of a plugin that has already been fetched.
+    pub fn manifest(&self, name: &str) -> Result<PluginManifest, Box<dyn std::error::Error>> {
+        let manifest_path = self.plugin_dir.join("build").join(name).join("plugin.toml");
+        let manifest = fs::read_to_string(&manifest_path)?;
+        Ok(toml::from_str(&manifest)?)
+    }
+
+    /// Registers a plugin (or changes the source and version of an already registered one)
+    /// and installs it.
+    pub fn add_and_install(
+        &mut self,
+        name: String,
+        source: String,
+        version: String,
+    ) -> Result<(), Error> {
+        match self.get_plugin_mut(&name) {
+            Some(plugin) => {
+                plugin.source = source;
+                plugin.set_version(version);
+            }
+            None => {
+                let mut plugin = Plugin::new(name.clone(), source);
+                plugin.set_version(version);
+                self.add_plugin(plugin);
+            }
+        }
+        self.install_plugin(name.clone())?;
+        self.save_installed(name)
+    }
 
+    /// Unregisters a plugin and deletes its build and install directories.
+    pub fn remove_plugin(&mut self, name: &str) -> Result<(), Box<dyn std::error::Error>> {
+        if self.get_plugin(name).is_none() {
+            return Err(format!("Plugin `{}` is not installed", name).into());
+        }
+
+        for dir in ["installed", "build"] {
+            let path = self.plugin_dir.join(dir).join(name);
+            if path.exists() {
+                fs::remove_dir_all(path)?;
+            }
+        }
+        self.plugins.retain(|p| p.name != name);
+        self.save()?;
         Ok(())
     }
 
@@ -204,21 +289,25 @@ impl PluginManager {
         name: String,
         source: String,
         version: Option<String>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let plugin = self.get_plugin(&name);
-        if plugin.is_none() {
-            let plugin_name = name.clone();
-            let mut plugin = Plugin::new(name, source);
+    ) -> Result<(), Error> {
+        if self.get_plugin(&name).is_none() {
+            let mut plugin = Plugin::new(name.clone(), source);
             plugin.set_version(version.unwrap_or("0.1.0".to_string()));
             self.add_plugin(plugin);
-            self.install_plugin(plugin_name)?;
-            self.save()?;
-        } else {
-            let plugin = plugin.unwrap();
-            self.install_plugin(plugin.name.clone())?;
-            self.save()?;
         }
-        Ok(())
+        self.install_plugin(name.clone())?;
+        self.save_installed(name)
+    }
+
+    fn save_installed(&self, name: String) -> Result<(), Error> {
+        self.save().map_err(|e| Error::PluginInstall {
+            source: self
+                .get_plugin(&name)
+                .map(|p| p.source.clone())
+                .unwrap_or_default(),
+            plugin: name,
+            cause: e.to_string().into(),
+        })
     }
 }
 
@@ -250,6 +339,20 @@ impl Plugin {
     }
 }
 
+fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
 fn is_git_url(source: &mut String) -> bool {
     if source.starts_with("http://") || source.starts_with("https://") || source.ends_with(".git") {
         return true;
@@ -283,11 +386,7 @@ fn print(state: &mut State) {
     let stats = state.progress.as_ref().unwrap();
     let network_pct = (100 * stats.received_objects()) / stats.total_objects();
     let index_pct = (100 * stats.indexed_objects()) / stats.total_objects();
-    let co_pct = if state.total > 0 {
-        (100 * state.current) / state.total
-    } else {
-        0
-    };
+    let co_pct = (100 * state.current).checked_div(state.total).unwrap_or(0);
     let kbytes = stats.received_bytes() / 1024;
     if stats.received_objects() == stats.total_objects() {
         if !state.newline {
@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+const BUILT_IN: [(&str, &str); 3] = [
+    ("build-test-deploy", include_str!("build-test-deploy.toml")),
+    ("backup", include_str!("backup.toml")),
+    ("plugin-hook-demo", include_str!("plugin-hook-demo.toml")),
+];
+
+#[derive(Debug, PartialEq)]
+pub struct Placeholder {
+    pub key: String,
+    pub default: Option<String>,
+}
+
+/// Loads a starter workflow for `xt create --template`, preferring `<templates_dir>/<kind>.toml`
+/// over the built-in ones. A starter is a workflow file containing `{{starter.KEY}}`
+/// placeholders, optionally with a default value (`{{starter.KEY|default}}`).
+pub fn load(templates_dir: &Path, kind: &str) -> Result<String, Box<dyn std::error::Error>> {
+    if let Ok(source) = fs::read_to_string(templates_dir.join(format!("{}.toml", kind))) {
+        return Ok(source);
+    }
+    match BUILT_IN.iter().find(|(name, _)| *name == kind) {
+        Some((_, source)) => Ok(source.to_string()),
+        None => Err(format!(
+            "Unknown template `{}`, available templates: {}",
+            kind,
+            available(templates_dir).join(", ")
+        )
+        .into()),
+    }
+}
+
+/// Names of every built-in and user-defined starter.
+pub fn available(templates_dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = BUILT_IN.iter().map(|(name, _)| name.to_string()).collect();
+    if let Ok(entries) = fs::read_dir(templates_dir) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().is_some_and(|e| e == "toml") {
+                if let Some(name) = path.file_stem() {
+                    names.push(name.to_string_lossy().to_string());
+                }
+            }
+        }
+    }
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Every distinct placeholder in a starter, in the order they first appear.
+pub fn placeholders(source: &str) -> Vec<Placeholder> {
+    let mut placeholders: Vec<Placeholder> = vec![];
+    for (_, key, default) in find_placeholders(source) {
+        if !placeholders.iter().any(|p| p.key == key) {
+            placeholders.push(Placeholder {
+                key: key.to_string(),
+                default: default.map(|d| d.to_string()),
+            });
+        }
+    }
+    placeholders
+}
+
+/// Replaces every placeholder with its value, falling back to the placeholder's default.
+/// Values are escaped so they can be used inside TOML strings.
+pub fn render(source: &str, values: &HashMap<String, String>) -> String {
+    let mut rendered = String::new();
+    let mut last = 0;
+    for (range, key, default) in find_placeholders(source) {
+        rendered.push_str(&source[last..range.start]);
+        let value = values
+            .get(key)
+            .map(|v| v.as_str())
+            .or(default)
+            .unwrap_or_default();
+        rendered.push_str(&value.replace('\\', "\\\\").replace('"', "\\\""));
+        last = range.end;
+    }
+    rendered.push_str(&source[last..]);
+    rendered
+}
+
+fn find_placeholders(source: &str) -> Vec<(std::ops::Range<usize>, &str, Option<&str>)> {
+    let mut found = vec![];
+    let mut offset = 0;
+    while let Some(start) = source[offset..].find("{{starter.") {
+        let start = offset + start;
+        let Some(end) = source[start..].find("}}") else {
+            break;
+        };
+        let end = start + end + 2;
+        let inner = &source[start + "{{starter.".len()..end - 2];
+        let (key, default) = match inner.split_once('|') {
+            Some((key, default)) => (key, Some(default)),
+            None => (inner, None),
+        };
+        found.push((start..end, key, default));
+        offset = end;
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workflow::structure::WorkFlow;
+
+    #[test]
+    fn test_placeholders() {
+        let source = "{{starter.name}} {{starter.dir|out}} {{starter.dir}} {{XTOMATE_TASK}}";
+        assert_eq!(
+            placeholders(source),
+            vec![
+                Placeholder {
+                    key: "name".to_string(),
+                    default: None,
+                },
+                Placeholder {
+                    key: "dir".to_string(),
+                    default: Some("out".to_string()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_render() {
+        let mut values = HashMap::new();
+        values.insert("name".to_string(), "say \"hi\"".to_string());
+        assert_eq!(
+            render(
+                "{{starter.name}} {{starter.dir|out}} {{XTOMATE_TASK}}",
+                &values
+            ),
+            "say \\\"hi\\\" out {{XTOMATE_TASK}}"
+        );
+    }
+
+    #[test]
+    fn test_built_in_starters_parse() {
+        let mut values = HashMap::new();
+        values.insert("name".to_string(), "test".to_string());
+        values.insert("version".to_string(), env!("CARGO_PKG_VERSION").to_string());
+        for (name, source) in BUILT_IN.iter() {
+            let workflow: Result<WorkFlow, _> = toml::from_str(&render(source, &values));
+            assert!(workflow.is_ok(), "starter `{}` does not parse", name);
+        }
+    }
+}
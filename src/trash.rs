@@ -0,0 +1,165 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Deleted workflows, kept in a directory under the XTomate data dir so they can be restored.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Trash {
+    pub entries: Vec<TrashEntry>,
+
+    #[serde(skip)]
+    trash_dir: PathBuf,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TrashEntry {
+    pub id: String,
+    pub name: String,
+    pub original_path: PathBuf,
+    pub deleted_at: u64,
+}
+
+impl Trash {
+    pub fn load_or_default(trash_dir: PathBuf) -> Result<Self, Box<dyn std::error::Error>> {
+        fs::create_dir_all(&trash_dir)?;
+        let mut trash = match fs::read_to_string(trash_dir.join("trash.toml")) {
+            Ok(trash) => toml::from_str(&trash)?,
+            Err(_) => Trash {
+                entries: vec![],
+                trash_dir: PathBuf::new(),
+            },
+        };
+        trash.trash_dir = trash_dir;
+        Ok(trash)
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let toml_string = toml::to_string(self)?;
+        let mut file = File::create(self.trash_dir.join("trash.toml"))?;
+        file.write_all(toml_string.as_bytes())?;
+        Ok(())
+    }
+
+    /// Moves the workflow file at `path` into the trash.
+    pub fn trash(&mut self, path: &Path) -> Result<&TrashEntry, Box<dyn std::error::Error>> {
+        if !path.is_file() {
+            return Err(format!("Workflow file `{}` does not exist", path.display()).into());
+        }
+
+        let name = path
+            .file_stem()
+            .ok_or("Workflow file has no name")?
+            .to_string_lossy()
+            .to_string();
+        let deleted_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
+        let mut id = format!("{}-{}", name, deleted_at);
+        let mut suffix = 1;
+        while self.get(&id).is_some() {
+            suffix += 1;
+            id = format!("{}-{}-{}", name, deleted_at, suffix);
+        }
+
+        let original_path = fs::canonicalize(path)?;
+        move_file(path, &self.entry_path(&id))?;
+        self.entries.push(TrashEntry {
+            id,
+            name,
+            original_path,
+            deleted_at,
+        });
+        self.save()?;
+
+        Ok(self.entries.last().unwrap())
+    }
+
+    /// Moves a trashed workflow back to where it was deleted from. `name` is either the id of
+    /// a trash entry or a workflow name, in which case the most recently deleted workflow with
+    /// that name is restored.
+    pub fn restore(
+        &mut self,
+        name: &str,
+        force: bool,
+    ) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        let index = self
+            .entries
+            .iter()
+            .rposition(|e| e.id == name)
+            .or_else(|| {
+                self.entries
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, e)| e.name == name)
+                    .max_by_key(|(_, e)| e.deleted_at)
+                    .map(|(index, _)| index)
+            })
+            .ok_or_else(|| format!("No workflow named `{}` in the trash", name))?;
+
+        let entry = &self.entries[index];
+        if entry.original_path.exists() && !force {
+            return Err(format!(
+                "`{}` already exists, use --force to overwrite it",
+                entry.original_path.display()
+            )
+            .into());
+        }
+
+        move_file(&self.entry_path(&entry.id), &entry.original_path)?;
+        let entry = self.entries.remove(index);
+        self.save()?;
+
+        Ok(entry.original_path)
+    }
+
+    pub fn get(&self, id: &str) -> Option<&TrashEntry> {
+        self.entries.iter().find(|e| e.id == id)
+    }
+
+    fn entry_path(&self, id: &str) -> PathBuf {
+        self.trash_dir.join(format!("{}.toml", id))
+    }
+}
+
+fn move_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    if fs::rename(from, to).is_err() {
+        // Renaming fails when the trash is on a different filesystem
+        fs::copy(from, to)?;
+        fs::remove_file(from)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trash_restore() {
+        let dir = std::env::temp_dir().join("xtomate-test-trash");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let workflow = dir.join("workflow.toml");
+        fs::write(&workflow, "name = \"workflow\"").unwrap();
+
+        let mut trash = Trash::load_or_default(dir.join("trash")).unwrap();
+        let id = trash.trash(&workflow).unwrap().id.clone();
+        assert!(!workflow.exists());
+        assert!(dir.join("trash").join(format!("{}.toml", id)).exists());
+
+        let trash_reloaded = Trash::load_or_default(dir.join("trash")).unwrap();
+        assert_eq!(trash_reloaded.entries.len(), 1);
+        assert_eq!(trash_reloaded.entries[0].name, "workflow");
+
+        fs::write(&workflow, "name = \"new\"").unwrap();
+        assert!(trash.restore("workflow", false).is_err());
+        trash.restore("workflow", true).unwrap();
+        assert_eq!(
+            fs::read_to_string(&workflow).unwrap(),
+            "name = \"workflow\""
+        );
+        assert!(trash.entries.is_empty());
+        assert!(trash.restore("workflow", false).is_err());
+    }
+}
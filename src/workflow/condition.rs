@@ -0,0 +1,280 @@
+use std::fmt;
+
+/// A parsed `when` expression, such as `env.CI == 'true' && tasks.test.status == 'success'`.
+///
+/// Every value is a string: variables (`env.NAME`, `params.NAME`, `tasks.NAME.status`,
+/// `tasks.NAME.outputs.OUTPUT` or any other placeholder name) are looked up when the
+/// expression is evaluated and are empty if they aren't set, and comparisons produce `true` or
+/// `false`. A value counts as true unless it is empty, `false` or `0`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Literal(String),
+    Variable(String),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Equal(Box<Expr>, Box<Expr>),
+    NotEqual(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Text(String),
+    Word(String),
+    Equal,
+    NotEqual,
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+impl Expr {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let tokens = tokenize(input)?;
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+        };
+        let expr = parser.or()?;
+        match parser.tokens.get(parser.position) {
+            None => Ok(expr),
+            Some(token) => Err(format!("unexpected {}", token)),
+        }
+    }
+
+    pub fn evaluate(&self, lookup: &dyn Fn(&str) -> Option<String>) -> bool {
+        truthy(&self.value(lookup))
+    }
+
+    /// The names of the variables used by the expression.
+    pub fn variables(&self) -> Vec<&str> {
+        match self {
+            Expr::Literal(_) => vec![],
+            Expr::Variable(name) => vec![name.as_str()],
+            Expr::Not(expr) => expr.variables(),
+            Expr::And(left, right)
+            | Expr::Or(left, right)
+            | Expr::Equal(left, right)
+            | Expr::NotEqual(left, right) => {
+                let mut variables = left.variables();
+                variables.extend(right.variables());
+                variables
+            }
+        }
+    }
+
+    fn value(&self, lookup: &dyn Fn(&str) -> Option<String>) -> String {
+        let boolean = |value: bool| value.to_string();
+        match self {
+            Expr::Literal(value) => value.clone(),
+            Expr::Variable(name) => lookup(name).unwrap_or_default(),
+            Expr::Not(expr) => boolean(!expr.evaluate(lookup)),
+            Expr::And(left, right) => boolean(left.evaluate(lookup) && right.evaluate(lookup)),
+            Expr::Or(left, right) => boolean(left.evaluate(lookup) || right.evaluate(lookup)),
+            Expr::Equal(left, right) => boolean(left.value(lookup) == right.value(lookup)),
+            Expr::NotEqual(left, right) => boolean(left.value(lookup) != right.value(lookup)),
+        }
+    }
+}
+
+fn truthy(value: &str) -> bool {
+    !matches!(value, "" | "false" | "0")
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        let token = match c {
+            ' ' | '\t' | '\n' | '\r' => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '=' | '&' | '|' if chars.peek() == Some(&c) => {
+                chars.next();
+                match c {
+                    '=' => Token::Equal,
+                    '&' => Token::And,
+                    _ => Token::Or,
+                }
+            }
+            '!' if chars.peek() == Some(&'=') => {
+                chars.next();
+                Token::NotEqual
+            }
+            '!' => Token::Not,
+            '\'' | '"' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some(end) if end == c => break,
+                        Some(c) => text.push(c),
+                        None => return Err(format!("unterminated string `{}{}`", c, text)),
+                    }
+                }
+                Token::Text(text)
+            }
+            c if is_word_char(c) => {
+                let mut word = c.to_string();
+                while let Some(&c) = chars.peek().filter(|c| is_word_char(**c)) {
+                    word.push(c);
+                    chars.next();
+                }
+                Token::Word(word)
+            }
+            c => return Err(format!("unexpected character `{}`", c)),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || "_.-".contains(c)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn next_if(&mut self, token: &Token) -> bool {
+        if self.tokens.get(self.position) == Some(token) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.next_if(&Token::Or) {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.not()?;
+        while self.next_if(&Token::And) {
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr, String> {
+        if self.next_if(&Token::Not) {
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        let left = self.primary()?;
+        if self.next_if(&Token::Equal) {
+            Ok(Expr::Equal(Box::new(left), Box::new(self.primary()?)))
+        } else if self.next_if(&Token::NotEqual) {
+            Ok(Expr::NotEqual(Box::new(left), Box::new(self.primary()?)))
+        } else {
+            Ok(left)
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .ok_or("unexpected end of expression")?;
+        self.position += 1;
+        match token {
+            Token::Open => {
+                let expr = self.or()?;
+                if !self.next_if(&Token::Close) {
+                    return Err("missing `)`".to_string());
+                }
+                Ok(expr)
+            }
+            Token::Text(text) => Ok(Expr::Literal(text)),
+            Token::Word(word) if word == "true" || word == "false" => Ok(Expr::Literal(word)),
+            Token::Word(word) if word.chars().all(|c| c.is_ascii_digit()) => {
+                Ok(Expr::Literal(word))
+            }
+            Token::Word(word) => Ok(Expr::Variable(word)),
+            token => Err(format!("unexpected {}", token)),
+        }
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Text(text) => write!(f, "string '{}'", text),
+            Token::Word(word) => write!(f, "`{}`", word),
+            Token::Equal => write!(f, "`==`"),
+            Token::NotEqual => write!(f, "`!=`"),
+            Token::And => write!(f, "`&&`"),
+            Token::Or => write!(f, "`||`"),
+            Token::Not => write!(f, "`!`"),
+            Token::Open => write!(f, "`(`"),
+            Token::Close => write!(f, "`)`"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn evaluate(input: &str) -> bool {
+        Expr::parse(input).unwrap().evaluate(&|name| match name {
+            "env.CI" => Some("true".to_string()),
+            "tasks.test.status" => Some("success".to_string()),
+            "params.target" => Some("".to_string()),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn test_evaluate() {
+        assert!(evaluate(
+            "env.CI == 'true' && tasks.test.status == 'success'"
+        ));
+        assert!(evaluate("env.CI"));
+        assert!(!evaluate("env.DEPLOY"));
+        assert!(!evaluate("params.target"));
+        assert!(evaluate("!params.target || env.DEPLOY"));
+        assert!(evaluate("tasks.test.status != \"failure\""));
+        assert!(!evaluate(
+            "env.CI == 'true' && !(tasks.test.status == 'success' || env.DEPLOY)"
+        ));
+        assert!(evaluate("env.DEPLOY == ''"));
+        assert!(!evaluate("0"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            Expr::parse("env.CI == 'true").unwrap_err(),
+            "unterminated string `'true`"
+        );
+        assert_eq!(
+            Expr::parse("env.CI &&").unwrap_err(),
+            "unexpected end of expression"
+        );
+        assert_eq!(Expr::parse("(env.CI").unwrap_err(), "missing `)`");
+        assert_eq!(
+            Expr::parse("env.CI env.DEPLOY").unwrap_err(),
+            "unexpected `env.DEPLOY`"
+        );
+        assert_eq!(
+            Expr::parse("env.CI = 1").unwrap_err(),
+            "unexpected character `=`"
+        );
+    }
+
+    #[test]
+    fn test_variables() {
+        let expr = Expr::parse("env.CI == 'true' && tasks.test.status == 'success'").unwrap();
+        assert_eq!(expr.variables(), vec!["env.CI", "tasks.test.status"]);
+    }
+}
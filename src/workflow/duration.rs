@@ -0,0 +1,130 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// A duration in a workflow file: either a number of seconds or a string like `500ms` or
+/// `1m30s`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum DurationValue {
+    Seconds(u64),
+    Text(String),
+}
+
+impl DurationValue {
+    pub fn to_duration(&self) -> Result<Duration, String> {
+        match self {
+            DurationValue::Seconds(seconds) => Ok(Duration::from_secs(*seconds)),
+            DurationValue::Text(text) => parse_duration(text),
+        }
+    }
+}
+
+/// Parses a human readable duration such as `500ms`, `30s`, `10m`, `2h` or `1h30m`.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let invalid = || {
+        format!(
+            "invalid duration `{}` (expected something like `30s`, `10m` or `1h30m`)",
+            value
+        )
+    };
+
+    let mut rest = value.trim();
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+
+    let mut total = Duration::ZERO;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let amount: u64 = rest[..digits].parse().map_err(|_| invalid())?;
+        rest = &rest[digits..];
+
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let unit = match &rest[..unit_len] {
+            "ms" => Duration::from_millis(1),
+            "s" => Duration::from_secs(1),
+            "m" => Duration::from_secs(60),
+            "h" => Duration::from_secs(60 * 60),
+            "d" => Duration::from_secs(24 * 60 * 60),
+            _ => return Err(invalid()),
+        };
+        rest = &rest[unit_len..];
+
+        let amount = u32::try_from(amount).map_err(|_| invalid())?;
+        total += unit * amount;
+    }
+    Ok(total)
+}
+
+/// Formats a duration the way `parse_duration` reads them, like `1h30m` or `2s500ms`.
+/// Milliseconds are only shown for durations under a minute.
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    let millis = if seconds < 60 {
+        duration.subsec_millis()
+    } else {
+        0
+    };
+    let parts = [
+        (seconds / (24 * 60 * 60), "d"),
+        (seconds / (60 * 60) % 24, "h"),
+        (seconds / 60 % 60, "m"),
+        (seconds % 60, "s"),
+        (millis as u64, "ms"),
+    ];
+    let formatted: String = parts
+        .iter()
+        .filter(|(amount, _)| *amount > 0)
+        .map(|(amount, unit)| format!("{}{}", amount, unit))
+        .collect();
+    if formatted.is_empty() {
+        "0s".to_string()
+    } else {
+        formatted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("10m"), Ok(Duration::from_secs(600)));
+        assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(5400)));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("10").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("5 minutes").is_err());
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::ZERO), "0s");
+        assert_eq!(format_duration(Duration::from_millis(250)), "250ms");
+        assert_eq!(format_duration(Duration::from_millis(2500)), "2s500ms");
+        assert_eq!(format_duration(Duration::from_millis(5_400_250)), "1h30m");
+        assert_eq!(format_duration(Duration::from_secs(90_061)), "1d1h1m1s");
+        assert_eq!(
+            parse_duration(&format_duration(Duration::from_millis(61_000))),
+            Ok(Duration::from_secs(61))
+        );
+    }
+
+    #[test]
+    fn test_duration_value() {
+        assert_eq!(
+            DurationValue::Seconds(2).to_duration(),
+            Ok(Duration::from_secs(2))
+        );
+        assert_eq!(
+            DurationValue::Text("250ms".to_string()).to_duration(),
+            Ok(Duration::from_millis(250))
+        );
+    }
+}
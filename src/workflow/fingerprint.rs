@@ -0,0 +1,161 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::runs::file_name;
+
+/// What is remembered about a task with `sources` when it succeeds.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Fingerprint {
+    /// The hash of the task's sources and resolved command.
+    pub hash: String,
+    /// The values of the task's outputs, by their `output_key`, so the tasks using them can
+    /// still run when it is up to date.
+    pub outputs: HashMap<String, String>,
+}
+
+/// The fingerprints of the tasks of one workflow, in `<dir>/<workflow>/<task>.toml`.
+pub struct FingerprintStore {
+    dir: PathBuf,
+}
+
+impl FingerprintStore {
+    pub fn new(dir: &Path, workflow: &str) -> Self {
+        FingerprintStore {
+            dir: dir.join(file_name(workflow)),
+        }
+    }
+
+    pub fn load(&self, task: &str) -> Option<Fingerprint> {
+        toml::from_str(&fs::read_to_string(self.path(task)).ok()?).ok()
+    }
+
+    pub fn save(
+        &self,
+        task: &str,
+        fingerprint: &Fingerprint,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        fs::create_dir_all(&self.dir)?;
+        fs::write(self.path(task), toml::to_string(fingerprint)?)?;
+        Ok(())
+    }
+
+    /// Forgets the fingerprint of a task, so it runs next time even if it fails now.
+    pub fn remove(&self, task: &str) {
+        let _ = fs::remove_file(self.path(task));
+    }
+
+    fn path(&self, task: &str) -> PathBuf {
+        self.dir.join(format!("{}.toml", file_name(task)))
+    }
+}
+
+/// Hashes the path and contents of every file matching the `sources` globs (relative to
+/// `dir`), together with `parts`: whatever else decides what the task does, like its resolved
+/// command and environment.
+pub fn hash(dir: &Path, sources: &[String], parts: &[String]) -> Result<String, String> {
+    let mut hasher = Sha256::new();
+    let mut update = |bytes: &[u8]| {
+        hasher.update((bytes.len() as u64).to_le_bytes());
+        hasher.update(bytes);
+    };
+    for part in parts {
+        update(part.as_bytes());
+    }
+    for path in expand(dir, sources)? {
+        if !path.is_file() {
+            continue;
+        }
+        let contents =
+            fs::read(&path).map_err(|e| format!("could not read `{}`: {}", path.display(), e))?;
+        update(path.to_string_lossy().as_bytes());
+        update(&contents);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+/// Whether every one of the `artifacts` globs (relative to `dir`) matches at least one path.
+pub fn artifacts_exist(dir: &Path, artifacts: &[String]) -> Result<bool, String> {
+    for artifact in artifacts {
+        if expand(dir, std::slice::from_ref(artifact))?.is_empty() {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Every path matching one of `patterns`, sorted and without duplicates.
+fn expand(dir: &Path, patterns: &[String]) -> Result<Vec<PathBuf>, String> {
+    let dir = glob::Pattern::escape(&dir.to_string_lossy());
+    let mut paths = vec![];
+    for pattern in patterns {
+        let full_pattern = Path::new(&dir).join(pattern);
+        let matches = glob::glob(&full_pattern.to_string_lossy())
+            .map_err(|e| format!("invalid glob `{}`: {}", pattern, e))?;
+        paths.extend(matches.filter_map(Result::ok));
+    }
+    paths.sort();
+    paths.dedup();
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash() {
+        let dir = std::env::temp_dir().join("xtomate-test-fingerprint");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(dir.join("src/main.c"), "int main() {}").unwrap();
+        fs::write(dir.join("src/util.c"), "void util() {}").unwrap();
+        fs::write(dir.join("README"), "hello").unwrap();
+
+        let sources = vec!["src/*.c".to_string()];
+        let command = vec!["cc src/*.c".to_string()];
+        let first = hash(&dir, &sources, &command).unwrap();
+        assert_eq!(first.len(), 64);
+        assert_eq!(hash(&dir, &sources, &command).unwrap(), first);
+
+        // Files that aren't sources don't matter, but sources and the command do
+        fs::write(dir.join("README"), "changed").unwrap();
+        assert_eq!(hash(&dir, &sources, &command).unwrap(), first);
+        assert_ne!(
+            hash(&dir, &sources, &["cc -O2 src/*.c".to_string()]).unwrap(),
+            first
+        );
+        fs::write(dir.join("src/util.c"), "void util(void) {}").unwrap();
+        assert_ne!(hash(&dir, &sources, &command).unwrap(), first);
+
+        assert!(artifacts_exist(&dir, &["src/*.c".to_string(), "README".to_string()]).unwrap());
+        assert!(!artifacts_exist(&dir, &["a.out".to_string()]).unwrap());
+        assert!(hash(&dir, &["src/[".to_string()], &command).is_err());
+    }
+
+    #[test]
+    fn test_store() {
+        let dir = std::env::temp_dir().join("xtomate-test-fingerprints");
+        let _ = fs::remove_dir_all(&dir);
+        let store = FingerprintStore::new(&dir, "my workflow");
+        assert_eq!(store.load("build"), None);
+
+        let fingerprint = Fingerprint {
+            hash: "abc".to_string(),
+            outputs: HashMap::from([(
+                "tasks.build.outputs.version".to_string(),
+                "1.0".to_string(),
+            )]),
+        };
+        store.save("build", &fingerprint).unwrap();
+        assert_eq!(store.load("build"), Some(fingerprint));
+        store.remove("build");
+        assert_eq!(store.load("build"), None);
+    }
+}
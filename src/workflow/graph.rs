@@ -0,0 +1,390 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use super::runner::parse_dependency;
+use super::structure::{Dependency, WorkFlow};
+
+/// The task graph of a workflow: one node per task (plus one for the workflow itself, which
+/// owns the workflow-level hooks), dependency edges pointing from a dependency to its
+/// dependent, and hook edges pointing from a task to the task its hook runs.
+pub struct TaskGraph {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+    indices: HashMap<String, usize>,
+}
+
+pub struct Node {
+    pub name: String,
+    pub kind: NodeKind,
+}
+
+#[derive(PartialEq)]
+pub enum NodeKind {
+    Workflow,
+    Task { run: bool },
+}
+
+pub struct Edge {
+    pub from: usize,
+    pub to: usize,
+    pub kind: EdgeKind,
+}
+
+#[derive(PartialEq)]
+pub enum EdgeKind {
+    /// The dependent requires the dependency to finish with this status.
+    Dependency(String),
+    /// The hook (`on_start`, `on_finish` or `on_error`) that runs the target task.
+    Hook(&'static str),
+    /// The dependent uses these outputs of the dependency, so it needs it to succeed.
+    Output(String),
+    /// The dependent's `when` condition uses the status or outputs of the dependency, so it has
+    /// to wait for it to finish.
+    Condition,
+}
+
+impl TaskGraph {
+    pub fn new(workflow: &WorkFlow) -> Self {
+        let mut graph = TaskGraph {
+            nodes: vec![Node {
+                name: workflow.name.clone(),
+                kind: NodeKind::Workflow,
+            }],
+            edges: vec![],
+            indices: HashMap::new(),
+        };
+
+        let mut names: Vec<&String> = workflow.get_tasks().keys().collect();
+        names.sort();
+        for name in names.iter() {
+            let task = workflow.get_task(name).unwrap();
+            graph.indices.insert(name.to_string(), graph.nodes.len());
+            graph.nodes.push(Node {
+                name: name.to_string(),
+                kind: NodeKind::Task {
+                    run: task.run.unwrap_or(true),
+                },
+            });
+        }
+
+        graph.add_hooks(0, "on_start", workflow.get_on_start());
+        graph.add_hooks(0, "on_finish", workflow.get_on_finish());
+
+        for name in names {
+            let task = workflow.get_task(name).unwrap();
+            let index = graph.indices[name.as_str()];
+
+            let mut dependencies = task.get_dependencies().cloned().unwrap_or_default();
+            if let Some(template) = task
+                .template
+                .as_ref()
+                .and_then(|t| workflow.get_template(t))
+            {
+                dependencies.extend(template.get_dependencies().cloned().unwrap_or_default());
+            }
+            for dependency in dependencies.iter() {
+                let (dependency, status) = match dependency {
+                    Dependency::Simple(dependency) => (dependency.as_str(), "success"),
+                    Dependency::Status(dep) => match dep.iter().next() {
+                        Some((dependency, status)) => {
+                            (dependency.as_str(), status.as_str().unwrap_or("success"))
+                        }
+                        None => continue,
+                    },
+                };
+                if let Some(&from) = graph.indices.get(dependency) {
+                    graph.edges.push(Edge {
+                        from,
+                        to: index,
+                        kind: EdgeKind::Dependency(status.to_string()),
+                    });
+                }
+            }
+
+            let mut outputs: Vec<(String, Vec<String>)> = vec![];
+            for (producer, output) in workflow.output_references(name) {
+                match outputs.last_mut() {
+                    Some((last, names)) if *last == producer => names.push(output),
+                    _ => outputs.push((producer, vec![output])),
+                }
+            }
+            for (producer, names) in outputs {
+                if let Some(&from) = graph.indices.get(&producer) {
+                    graph.edges.push(Edge {
+                        from,
+                        to: index,
+                        kind: EdgeKind::Output(names.join(", ")),
+                    });
+                }
+            }
+
+            for producer in workflow.condition_references(name) {
+                if let Some(&from) = graph.indices.get(&producer) {
+                    graph.edges.push(Edge {
+                        from,
+                        to: index,
+                        kind: EdgeKind::Condition,
+                    });
+                }
+            }
+
+            graph.add_hooks(index, "on_start", task.get_on_start());
+            graph.add_hooks(index, "on_finish", task.get_on_finish());
+            graph.add_hooks(index, "on_error", task.get_on_error());
+        }
+
+        graph
+    }
+
+    fn add_hooks(&mut self, from: usize, hook: &'static str, hooks: Option<&Vec<Dependency>>) {
+        for dependency in hooks.unwrap_or(&vec![]) {
+            let target = match dependency {
+                Dependency::Simple(target) => target.as_str(),
+                Dependency::Status(dep) => match dep.keys().next() {
+                    Some(target) => target.as_str(),
+                    None => continue,
+                },
+            };
+            if let ("task", task) = parse_dependency(target) {
+                if let Some(&to) = self.indices.get(task) {
+                    self.edges.push(Edge {
+                        from,
+                        to,
+                        kind: EdgeKind::Hook(hook),
+                    });
+                }
+            }
+        }
+    }
+
+    /// Names of the tasks `task` depends on (including the tasks whose outputs it uses), or
+    /// `None` if the workflow has no such task.
+    pub fn dependencies(&self, task: &str) -> Option<Vec<String>> {
+        let index = *self.indices.get(task)?;
+        let mut dependencies: Vec<String> = vec![];
+        for edge in self.edges.iter() {
+            if edge.to == index && !matches!(edge.kind, EdgeKind::Hook(_)) {
+                let name = &self.nodes[edge.from].name;
+                if !dependencies.contains(name) {
+                    dependencies.push(name.clone());
+                }
+            }
+        }
+        Some(dependencies)
+    }
+
+    /// The hooks of `task` that run another task, as `(hook, target)` pairs, or `None` if the
+    /// workflow has no such task.
+    pub fn hooks(&self, task: &str) -> Option<Vec<(&'static str, String)>> {
+        let index = *self.indices.get(task)?;
+        Some(
+            self.edges
+                .iter()
+                .filter_map(|edge| match edge.kind {
+                    EdgeKind::Hook(hook) if edge.from == index => {
+                        Some((hook, self.nodes[edge.to].name.clone()))
+                    }
+                    _ => None,
+                })
+                .collect(),
+        )
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut dot = format!("digraph {} {{\n", quote(&self.nodes[0].name));
+        dot.push_str("    rankdir=LR;\n");
+        dot.push_str("    node [shape=box];\n");
+        for (index, node) in self.nodes.iter().enumerate() {
+            let style = match node.kind {
+                NodeKind::Workflow => ", shape=ellipse, style=bold",
+                NodeKind::Task { run: false } => ", style=dashed, fontcolor=gray40",
+                NodeKind::Task { run: true } => "",
+            };
+            let _ = writeln!(
+                dot,
+                "    n{} [label={}{}];",
+                index,
+                quote(&node.name),
+                style
+            );
+        }
+        for edge in self.edges.iter() {
+            let (label, style) = match &edge.kind {
+                EdgeKind::Dependency(status) => (status.as_str(), ""),
+                EdgeKind::Hook(hook) => (*hook, ", style=dashed"),
+                EdgeKind::Output(outputs) => (outputs.as_str(), ", style=bold"),
+                EdgeKind::Condition => ("when", ", style=dotted"),
+            };
+            let _ = writeln!(
+                dot,
+                "    n{} -> n{} [label={}{}];",
+                edge.from,
+                edge.to,
+                quote(label),
+                style
+            );
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    pub fn to_mermaid(&self) -> String {
+        let mut mermaid = "flowchart LR\n".to_string();
+        for (index, node) in self.nodes.iter().enumerate() {
+            let label = node.name.replace('"', "#quot;");
+            let _ = match node.kind {
+                NodeKind::Workflow => writeln!(mermaid, "    n{}([\"{}\"])", index, label),
+                NodeKind::Task { run: false } => {
+                    writeln!(mermaid, "    n{}[\"{}\"]:::manual", index, label)
+                }
+                NodeKind::Task { run: true } => writeln!(mermaid, "    n{}[\"{}\"]", index, label),
+            };
+        }
+        for edge in self.edges.iter() {
+            let _ = match &edge.kind {
+                EdgeKind::Dependency(status) => {
+                    writeln!(mermaid, "    n{} -->|{}| n{}", edge.from, status, edge.to)
+                }
+                EdgeKind::Hook(hook) => {
+                    writeln!(mermaid, "    n{} -.->|{}| n{}", edge.from, hook, edge.to)
+                }
+                EdgeKind::Output(outputs) => {
+                    writeln!(mermaid, "    n{} ==>|{}| n{}", edge.from, outputs, edge.to)
+                }
+                EdgeKind::Condition => {
+                    writeln!(mermaid, "    n{} -.-o|when| n{}", edge.from, edge.to)
+                }
+            };
+        }
+        mermaid.push_str("    classDef manual stroke-dasharray: 5 5,color:#666\n");
+        mermaid
+    }
+}
+
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn workflow() -> WorkFlow {
+        toml::from_str(
+            r#"
+            name = "test"
+            version = "0.1.0"
+            on_finish = ["clean"]
+
+            [tasks.clean]
+            run = false
+            command = "rm -rf out"
+
+            [tasks.build]
+            command = "make"
+            on_error = ["clean"]
+
+            [tasks.test]
+            command = "make test"
+            dependencies = [{"build" = "any"}]
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_dependencies() {
+        let graph = TaskGraph::new(&workflow());
+        assert_eq!(graph.dependencies("test"), Some(vec!["build".to_string()]));
+        assert_eq!(graph.dependencies("build"), Some(vec![]));
+        assert_eq!(graph.dependencies("missing"), None);
+    }
+
+    #[test]
+    fn test_output_edges() {
+        let workflow: WorkFlow = toml::from_str(
+            r#"
+            name = "test"
+            version = "0.1.0"
+
+            [tasks.build]
+            command = "make"
+            outputs = { version = { regex = "version (.+)" }, log = "stdout" }
+
+            [tasks.deploy]
+            command = "deploy {{tasks.build.outputs.version}}"
+            dependencies = ["build"]
+            env = { LOG = "{{tasks.build.outputs.log}}" }
+            "#,
+        )
+        .unwrap();
+        let graph = TaskGraph::new(&workflow);
+        assert_eq!(
+            graph.dependencies("deploy"),
+            Some(vec!["build".to_string()])
+        );
+        assert!(graph.to_mermaid().contains("n1 ==>|log, version| n2"));
+    }
+
+    #[test]
+    fn test_condition_edges() {
+        let workflow: WorkFlow = toml::from_str(
+            r#"
+            name = "test"
+            version = "0.1.0"
+
+            [tasks.test]
+            command = "cargo test"
+
+            [tasks.deploy]
+            command = "deploy"
+            when = "env.CI == 'true' && tasks.test.status == 'success'"
+            "#,
+        )
+        .unwrap();
+        let graph = TaskGraph::new(&workflow);
+        assert_eq!(graph.dependencies("deploy"), Some(vec!["test".to_string()]));
+        assert!(graph
+            .to_dot()
+            .contains("n2 -> n1 [label=\"when\", style=dotted];"));
+        assert!(graph.to_mermaid().contains("n2 -.-o|when| n1"));
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let graph = TaskGraph::new(&workflow());
+        assert_eq!(
+            graph.to_dot(),
+            r#"digraph "test" {
+    rankdir=LR;
+    node [shape=box];
+    n0 [label="test", shape=ellipse, style=bold];
+    n1 [label="build"];
+    n2 [label="clean", style=dashed, fontcolor=gray40];
+    n3 [label="test"];
+    n0 -> n2 [label="on_finish", style=dashed];
+    n1 -> n2 [label="on_error", style=dashed];
+    n1 -> n3 [label="any"];
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_to_mermaid() {
+        let graph = TaskGraph::new(&workflow());
+        assert_eq!(
+            graph.to_mermaid(),
+            r#"flowchart LR
+    n0(["test"])
+    n1["build"]
+    n2["clean"]:::manual
+    n3["test"]
+    n0 -.->|on_finish| n2
+    n1 -.->|on_error| n2
+    n1 -->|any| n3
+    classDef manual stroke-dasharray: 5 5,color:#666
+"#
+        );
+    }
+}
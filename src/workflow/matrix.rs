@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+use toml::{Table, Value};
+
+use super::structure::Dependency;
+
+/// Every combination of the values of a task's `matrix`, as `(variable, value)` pairs sorted
+/// by variable.
+pub fn combinations(matrix: &Table) -> Result<Vec<Vec<(String, String)>>, String> {
+    if matrix.is_empty() {
+        return Err("must have at least one variable".to_string());
+    }
+    let mut combinations: Vec<Vec<(String, String)>> = vec![vec![]];
+    for (variable, values) in matrix {
+        let Value::Array(values) = values else {
+            return Err(format!("`{}` must be a list of values", variable));
+        };
+        if values.is_empty() {
+            return Err(format!("`{}` has no values", variable));
+        }
+        let mut values_as_strings = vec![];
+        for value in values {
+            match value {
+                Value::String(s) => values_as_strings.push(s.clone()),
+                Value::Array(_) | Value::Table(_) => {
+                    return Err(format!(
+                        "`{}` can only contain strings, numbers and booleans",
+                        variable
+                    ))
+                }
+                value => values_as_strings.push(value.to_string()),
+            }
+        }
+        combinations = combinations
+            .into_iter()
+            .flat_map(|combination| {
+                values_as_strings.iter().map(move |value| {
+                    let mut combination = combination.clone();
+                    combination.push((variable.clone(), value.clone()));
+                    combination
+                })
+            })
+            .collect();
+    }
+    Ok(combinations)
+}
+
+/// The name of one instance of a matrix task, e.g. `build[profile=debug,target=x86_64]`.
+pub fn instance_name(task: &str, combination: &[(String, String)]) -> String {
+    let values: Vec<String> = combination
+        .iter()
+        .map(|(variable, value)| format!("{}={}", variable, value))
+        .collect();
+    format!("{}[{}]", task, values.join(","))
+}
+
+/// Replaces every dependency or `task:` hook on a matrix task with one for each of its
+/// instances.
+pub fn expand_references(
+    references: Option<&mut Vec<Dependency>>,
+    instances: &HashMap<String, Vec<String>>,
+) {
+    let Some(references) = references else {
+        return;
+    };
+    *references = references
+        .drain(..)
+        .flat_map(|reference| {
+            let target = match &reference {
+                Dependency::Simple(target) => target.clone(),
+                Dependency::Status(table) => match table.keys().next() {
+                    Some(target) => target.clone(),
+                    None => return vec![reference],
+                },
+            };
+            let (prefix, task) = match target.strip_prefix("task:") {
+                Some(task) => ("task:", task),
+                None => ("", target.as_str()),
+            };
+            let Some(names) = instances.get(task) else {
+                return vec![reference];
+            };
+            names
+                .iter()
+                .map(|name| {
+                    let target = format!("{}{}", prefix, name);
+                    match &reference {
+                        Dependency::Simple(_) => Dependency::Simple(target),
+                        Dependency::Status(table) => {
+                            let mut table = table.clone();
+                            let value = table.remove(&format!("{}{}", prefix, task)).unwrap();
+                            table.insert(target, value);
+                            Dependency::Status(table)
+                        }
+                    }
+                })
+                .collect()
+        })
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_combinations() {
+        let matrix: Table =
+            toml::from_str("target = [\"x86_64\", \"aarch64\"]\nopt = [1, 3]").unwrap();
+        let names: Vec<String> = combinations(&matrix)
+            .unwrap()
+            .iter()
+            .map(|combination| instance_name("build", combination))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "build[opt=1,target=x86_64]",
+                "build[opt=1,target=aarch64]",
+                "build[opt=3,target=x86_64]",
+                "build[opt=3,target=aarch64]",
+            ]
+        );
+
+        let invalid = |source: &str| combinations(&toml::from_str(source).unwrap()).unwrap_err();
+        assert_eq!(invalid(""), "must have at least one variable");
+        assert_eq!(
+            invalid("target = \"x86_64\""),
+            "`target` must be a list of values"
+        );
+        assert_eq!(invalid("target = []"), "`target` has no values");
+        assert_eq!(
+            invalid("target = [[\"x86_64\"]]"),
+            "`target` can only contain strings, numbers and booleans"
+        );
+    }
+
+    #[test]
+    fn test_expand_references() {
+        let instances = HashMap::from([(
+            "build".to_string(),
+            vec!["build[a=1]".to_string(), "build[a=2]".to_string()],
+        )]);
+        let mut references: Vec<Dependency> =
+            toml::from_str::<Table>(r#"list = ["lint", "task:build", { build = "any" }]"#).unwrap()
+                ["list"]
+                .clone()
+                .try_into()
+                .unwrap();
+        expand_references(Some(&mut references), &instances);
+        let status = |task: &str| {
+            let mut table = Table::new();
+            table.insert(task.to_string(), Value::String("any".to_string()));
+            Dependency::Status(table)
+        };
+        assert_eq!(
+            references,
+            vec![
+                Dependency::Simple("lint".to_string()),
+                Dependency::Simple("task:build[a=1]".to_string()),
+                Dependency::Simple("task:build[a=2]".to_string()),
+                status("build[a=1]"),
+                status("build[a=2]"),
+            ]
+        );
+    }
+}
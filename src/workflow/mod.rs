@@ -1,3 +1,14 @@
+pub mod condition;
+pub mod duration;
+pub mod fingerprint;
+pub mod graph;
+pub mod matrix;
+pub mod params;
 pub mod placeholders;
+pub mod process;
+pub mod result;
+pub mod retry;
 pub mod runner;
+pub mod runs;
 pub mod structure;
+pub mod validate;
@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+use toml::Value;
+
+use super::structure::{Param, ParamType};
+use super::validate::{Issue, Scope};
+
+/// Checks a value given for a parameter and returns it in its canonical form: integers
+/// without leading zeros or `+`, and booleans as `true` or `false`.
+pub fn parse_value(param: &Param, value: &str) -> Result<String, String> {
+    match param.kind {
+        ParamType::String => Ok(value.to_string()),
+        ParamType::Int => value
+            .trim()
+            .parse::<i64>()
+            .map(|value| value.to_string())
+            .map_err(|_| format!("`{}` is not an integer", value)),
+        ParamType::Bool => match value.trim().to_lowercase().as_str() {
+            "true" | "yes" | "1" => Ok("true".to_string()),
+            "false" | "no" | "0" => Ok("false".to_string()),
+            _ => Err(format!("`{}` is not a boolean (true or false)", value)),
+        },
+        ParamType::Enum => {
+            let values = param.values.as_deref().unwrap_or_default();
+            if values.iter().any(|allowed| allowed == value) {
+                Ok(value.to_string())
+            } else {
+                Err(format!("`{}` is not one of: {}", value, values.join(", ")))
+            }
+        }
+    }
+}
+
+/// A value from a params file or a `default`, as it would be given on the command line.
+pub fn value_to_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Integer(i) => Some(i.to_string()),
+        Value::Boolean(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+/// The value of every declared parameter: the one given in `values`, or else its default.
+/// Parameters that aren't required and have no default are empty.
+pub fn resolve(
+    params: &HashMap<String, Param>,
+    values: &[(String, String)],
+) -> Result<Vec<(String, String)>, Vec<Issue>> {
+    let mut issues = vec![];
+    let mut resolved = vec![];
+
+    for (name, _) in values.iter() {
+        if !params.contains_key(name) {
+            issues.push(Issue {
+                scope: Scope::Workflow,
+                message: format!("no parameter named `{}`", name),
+            });
+        }
+    }
+
+    let mut names: Vec<&String> = params.keys().collect();
+    names.sort();
+    for name in names {
+        let param = &params[name];
+        let scope = Scope::Param(name.clone());
+        // A value given later (on the command line) overrides one given earlier (in a file)
+        let given = values.iter().rev().find(|(n, _)| n == name);
+        let value = match (given, &param.default) {
+            (Some((_, value)), _) => value.clone(),
+            (None, Some(default)) => value_to_string(default).unwrap_or_default(),
+            (None, None) if param.required.unwrap_or(false) => {
+                issues.push(Issue {
+                    scope,
+                    message: "is required (set it with --param NAME=VALUE)".to_string(),
+                });
+                continue;
+            }
+            (None, None) => {
+                resolved.push((name.clone(), String::new()));
+                continue;
+            }
+        };
+        match parse_value(param, &value) {
+            Ok(value) => resolved.push((name.clone(), value)),
+            Err(message) => issues.push(Issue { scope, message }),
+        }
+    }
+
+    if issues.is_empty() {
+        Ok(resolved)
+    } else {
+        Err(issues)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params() -> HashMap<String, Param> {
+        toml::from_str(
+            r#"
+            env = { type = "enum", values = ["staging", "prod"], required = true }
+            replicas = { type = "int", default = 2 }
+            dry_run = { type = "bool", default = false, description = "Only print what would change" }
+            tag = {}
+            "#,
+        )
+        .unwrap()
+    }
+
+    fn values(values: &[(&str, &str)]) -> Vec<(String, String)> {
+        values
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_resolve() {
+        assert_eq!(
+            resolve(
+                &params(),
+                &values(&[("env", "staging"), ("dry_run", "yes"), ("env", "prod")])
+            ),
+            Ok(values(&[
+                ("dry_run", "true"),
+                ("env", "prod"),
+                ("replicas", "2"),
+                ("tag", ""),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_resolve_errors() {
+        let messages: Vec<String> = resolve(
+            &params(),
+            &values(&[("replicas", "many"), ("dry_run", "maybe"), ("region", "eu")]),
+        )
+        .unwrap_err()
+        .iter()
+        .map(|issue| issue.to_string())
+        .collect();
+        assert_eq!(
+            messages,
+            [
+                "workflow: no parameter named `region`",
+                "param `dry_run`: `maybe` is not a boolean (true or false)",
+                "param `env`: is required (set it with --param NAME=VALUE)",
+                "param `replicas`: `many` is not an integer",
+            ]
+        );
+
+        let messages: Vec<String> = resolve(&params(), &values(&[("env", "dev")]))
+            .unwrap_err()
+            .iter()
+            .map(|issue| issue.to_string())
+            .collect();
+        assert_eq!(
+            messages,
+            ["param `env`: `dev` is not one of: staging, prod"]
+        );
+    }
+}
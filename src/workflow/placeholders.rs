@@ -1,8 +1,13 @@
+use regex::Regex;
 use std::collections::HashMap;
+use std::sync::LazyLock;
 
 use toml::Table;
 
-#[derive(Default)]
+static OUTPUT_REFERENCE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\{\{tasks\.([^.{}]+)\.outputs\.([^.{}]+)\}\}").unwrap());
+
+#[derive(Default, Clone)]
 pub struct Context {
     variables: HashMap<String, String>,
 }
@@ -18,6 +23,10 @@ impl Context {
         self.variables.insert(key, value);
     }
 
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.variables.get(key).map(|value| value.as_str())
+    }
+
     pub fn resolve(&self, input: &str) -> String {
         let mut resolved = input.to_string();
         for (key, value) in &self.variables {
@@ -45,6 +54,19 @@ impl Context {
     }
 }
 
+/// The `(task, output)` of every `{{tasks.TASK.outputs.OUTPUT}}` placeholder in `input`.
+pub fn output_references(input: &str) -> Vec<(String, String)> {
+    OUTPUT_REFERENCE
+        .captures_iter(input)
+        .map(|captures| (captures[1].to_string(), captures[2].to_string()))
+        .collect()
+}
+
+/// The key of an output in a `Context`, so that it replaces `{{tasks.TASK.outputs.OUTPUT}}`.
+pub fn output_key(task: &str, output: &str) -> String {
+    format!("tasks.{}.outputs.{}", task, output)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -58,6 +80,20 @@ mod tests {
         assert_eq!(context.resolve("{{key}} {{key2}}"), "value {{key2}}");
     }
 
+    #[test]
+    fn test_output_references() {
+        assert_eq!(
+            output_references("deploy {{tasks.build.outputs.version}} {{XTOMATE_TASK}}"),
+            vec![("build".to_string(), "version".to_string())]
+        );
+        let mut context = Context::new();
+        context.set(output_key("build", "version"), "1.2.0".to_string());
+        assert_eq!(
+            context.resolve("deploy {{tasks.build.outputs.version}}"),
+            "deploy 1.2.0"
+        );
+    }
+
     #[test]
     fn test_context_table() {
         let mut context = Context::new();
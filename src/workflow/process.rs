@@ -0,0 +1,407 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::Command;
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+use super::result::TaskStatus;
+
+/// How long a command gets to exit after being signalled before it is killed with SIGKILL,
+/// unless the workflow sets `grace_period`.
+pub const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Signal {
+    Interrupt,
+    Terminate,
+}
+
+/// The process groups of every running command, so they can all be signalled when the run is
+/// interrupted.
+pub struct Processes {
+    groups: Mutex<HashSet<u32>>,
+    interrupted: watch::Sender<Option<Signal>>,
+    grace_period: Duration,
+}
+
+/// Which of a command's output streams a line was written to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+pub struct Output {
+    pub status: TaskStatus,
+    /// The exit code of the command, unless it was killed.
+    pub exit_code: Option<i32>,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+impl Processes {
+    pub fn new(grace_period: Duration) -> Self {
+        Processes {
+            groups: Mutex::new(HashSet::new()),
+            interrupted: watch::Sender::new(None),
+            grace_period,
+        }
+    }
+
+    /// Forwards `signal` to every running command. Each of them is killed if it is still
+    /// running after the grace period.
+    pub fn interrupt(&self, signal: Signal) {
+        self.interrupted.send_replace(Some(signal));
+        for group in self.groups.lock().expect("Failed to lock mutex").iter() {
+            send_signal(Some(*group), signal.raw());
+        }
+    }
+
+    pub fn interrupted(&self) -> Option<Signal> {
+        *self.interrupted.borrow()
+    }
+
+    /// Kills every running command immediately.
+    pub fn kill_all(&self) {
+        for group in self.groups.lock().expect("Failed to lock mutex").iter() {
+            send_signal(Some(*group), SIGKILL);
+        }
+    }
+
+    /// Runs the program and arguments in `argv` (in `cwd`, if given) in its own process group
+    /// and collects its output, passing every line to `on_line` as soon as it is written. If
+    /// the command is still running at `deadline`, or the run is interrupted while it is
+    /// running, the whole process group is stopped and the output collected so far is
+    /// returned with `TaskStatus::TimedOut` or `TaskStatus::Interrupted`.
+    pub async fn run(
+        &self,
+        argv: &[String],
+        cwd: Option<&Path>,
+        env: &[(String, String)],
+        deadline: Option<Instant>,
+        on_line: impl Fn(Stream, &[u8]),
+    ) -> Output {
+        let mut output = Output {
+            status: TaskStatus::Failed,
+            exit_code: None,
+            stdout: vec![],
+            stderr: vec![],
+        };
+
+        let Some((program, args)) = argv.split_first() else {
+            output.stderr = b"Nothing to run\n".to_vec();
+            return output;
+        };
+        let mut command = Command::new(program);
+        command
+            .args(args)
+            .envs(env.iter().cloned())
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        if let Some(cwd) = cwd {
+            command.current_dir(cwd);
+        }
+        #[cfg(unix)]
+        command.process_group(0);
+
+        let mut interrupted = self.interrupted.subscribe();
+        let mut child = match command.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                let place = cwd
+                    .map(|cwd| format!(" in `{}`", cwd.display()))
+                    .unwrap_or_default();
+                output.stderr =
+                    format!("Failed to start `{}`{}: {}\n", program, place, e).into_bytes();
+                return output;
+            }
+        };
+        let group = child.id();
+        if let Some(group) = group {
+            self.groups
+                .lock()
+                .expect("Failed to lock mutex")
+                .insert(group);
+        }
+
+        let mut stdout = child.stdout.take();
+        let mut stderr = child.stderr.take();
+        let mut exit_code = None;
+        output.status = {
+            let finished = async {
+                let (_, _, status) = tokio::join!(
+                    read_lines(stdout.as_mut(), &mut output.stdout, |line| {
+                        on_line(Stream::Stdout, line)
+                    }),
+                    read_lines(stderr.as_mut(), &mut output.stderr, |line| {
+                        on_line(Stream::Stderr, line)
+                    }),
+                    child.wait()
+                );
+                exit_code = status.as_ref().ok().and_then(|status| status.code());
+                match status {
+                    Ok(status) if status.success() => TaskStatus::Success,
+                    _ => TaskStatus::Failed,
+                }
+            };
+            tokio::pin!(finished);
+            let timed_out = async {
+                match deadline {
+                    Some(deadline) => tokio::time::sleep_until(deadline).await,
+                    None => std::future::pending().await,
+                }
+            };
+            let interrupt = async {
+                // Only interruptions that happen while the command runs count
+                while interrupted.changed().await.is_ok() {
+                    if interrupted.borrow().is_some() {
+                        return;
+                    }
+                }
+                std::future::pending().await
+            };
+
+            let status = tokio::select! {
+                status = &mut finished => Ok(status),
+                _ = timed_out => Err(TaskStatus::TimedOut),
+                _ = interrupt => Err(TaskStatus::Interrupted),
+            };
+            match status {
+                Ok(status) => status,
+                Err(status) => {
+                    // An interrupted command has already been sent the signal
+                    if status == TaskStatus::TimedOut {
+                        send_signal(group, SIGTERM);
+                    }
+                    // Keep collecting output while the command shuts down
+                    if tokio::time::timeout(self.grace_period, &mut finished)
+                        .await
+                        .is_err()
+                    {
+                        send_signal(group, SIGKILL);
+                        let _ = tokio::time::timeout(self.grace_period, &mut finished).await;
+                    }
+                    // Commands that outlived the shell
+                    send_signal(group, SIGKILL);
+                    status
+                }
+            }
+        };
+
+        if let Some(group) = group {
+            self.groups
+                .lock()
+                .expect("Failed to lock mutex")
+                .remove(&group);
+        }
+        if matches!(output.status, TaskStatus::Success | TaskStatus::Failed) {
+            output.exit_code = exit_code;
+        }
+        output
+    }
+}
+
+impl Signal {
+    fn raw(self) -> i32 {
+        match self {
+            Signal::Interrupt => SIGINT,
+            Signal::Terminate => SIGTERM,
+        }
+    }
+}
+
+/// Waits for Ctrl-C (SIGINT) or SIGTERM.
+pub async fn wait_for_signal() -> Signal {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        if let Ok(mut terminate) = signal(SignalKind::terminate()) {
+            return tokio::select! {
+                _ = tokio::signal::ctrl_c() => Signal::Interrupt,
+                _ = terminate.recv() => Signal::Terminate,
+            };
+        }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+    Signal::Interrupt
+}
+
+#[cfg(unix)]
+use libc::{SIGINT, SIGKILL, SIGTERM};
+#[cfg(not(unix))]
+const SIGINT: i32 = 2;
+#[cfg(not(unix))]
+const SIGKILL: i32 = 9;
+#[cfg(not(unix))]
+const SIGTERM: i32 = 15;
+
+/// Sends `signal` to every process in the group. Does nothing on platforms without process
+/// groups.
+fn send_signal(group: Option<u32>, signal: i32) {
+    #[cfg(unix)]
+    if let Some(group) = group {
+        unsafe {
+            libc::killpg(group as libc::pid_t, signal);
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = (group, signal);
+}
+
+/// Reads everything from `reader` into `buffer`, calling `on_line` with every line (including
+/// its newline, if it has one) as soon as it is complete.
+async fn read_lines<R: AsyncRead + Unpin>(
+    reader: Option<&mut R>,
+    buffer: &mut Vec<u8>,
+    on_line: impl Fn(&[u8]),
+) {
+    if let Some(reader) = reader {
+        let mut reader = BufReader::new(reader);
+        loop {
+            let start = buffer.len();
+            match reader.read_until(b'\n', buffer).await {
+                Ok(0) | Err(_) => break,
+                Ok(_) => on_line(&buffer[start..]),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn sh(command: &str) -> Vec<String> {
+        vec!["sh".to_string(), "-c".to_string(), command.to_string()]
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let processes = Processes::new(DEFAULT_GRACE_PERIOD);
+        let output = processes
+            .run(
+                &sh("echo $GREETING; echo oops >&2"),
+                None,
+                &[("GREETING".to_string(), "hi".to_string())],
+                None,
+                |_, _| {},
+            )
+            .await;
+        assert_eq!(output.status, TaskStatus::Success);
+        assert_eq!(output.stdout, b"hi\n");
+        assert_eq!(output.stderr, b"oops\n");
+        assert_eq!(output.exit_code, Some(0));
+
+        let output = processes
+            .run(&sh("exit 3"), None, &[], None, |_, _| {})
+            .await;
+        assert_eq!(output.status, TaskStatus::Failed);
+        assert_eq!(output.exit_code, Some(3));
+    }
+
+    #[tokio::test]
+    async fn test_run_argv_and_cwd() {
+        let processes = Processes::new(DEFAULT_GRACE_PERIOD);
+        let dir = std::env::temp_dir().canonicalize().unwrap();
+        let argv: Vec<String> = ["sh", "-c", "pwd; printf '%s|' \"$@\"", "sh", "a b", "*"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        let output = processes.run(&argv, Some(&dir), &[], None, |_, _| {}).await;
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            format!("{}\na b|*|", dir.display())
+        );
+
+        let output = processes
+            .run(&["no-such-program".to_string()], None, &[], None, |_, _| {})
+            .await;
+        assert_eq!(output.status, TaskStatus::Failed);
+        assert!(String::from_utf8(output.stderr)
+            .unwrap()
+            .starts_with("Failed to start `no-such-program`: "));
+    }
+
+    #[tokio::test]
+    async fn test_run_streams_lines() {
+        let processes = Processes::new(DEFAULT_GRACE_PERIOD);
+        let started = Instant::now();
+        let lines = Mutex::new(vec![]);
+        let output = processes
+            .run(
+                &sh("echo one; echo two >&2; sleep 0.5; printf three"),
+                None,
+                &[],
+                None,
+                |stream, line| {
+                    lines
+                        .lock()
+                        .unwrap()
+                        .push((stream, line.to_vec(), started.elapsed()))
+                },
+            )
+            .await;
+        assert_eq!(output.stdout, b"one\nthree");
+
+        let lines = lines.into_inner().unwrap();
+        let lines_of = |stream: Stream| -> Vec<&[u8]> {
+            lines
+                .iter()
+                .filter(|(s, _, _)| *s == stream)
+                .map(|(_, line, _)| line.as_slice())
+                .collect()
+        };
+        assert_eq!(lines_of(Stream::Stdout), vec![&b"one\n"[..], &b"three"[..]]);
+        assert_eq!(lines_of(Stream::Stderr), vec![&b"two\n"[..]]);
+        // The first lines arrive while the command is still running
+        assert!(lines[0].2 < Duration::from_millis(400));
+    }
+
+    #[tokio::test]
+    async fn test_run_timeout() {
+        let processes = Processes::new(DEFAULT_GRACE_PERIOD);
+        let started = Instant::now();
+        let output = processes
+            .run(
+                &sh("echo started; sleep 10 & sleep 10; echo finished"),
+                None,
+                &[],
+                Some(Instant::now() + Duration::from_millis(200)),
+                |_, _| {},
+            )
+            .await;
+        assert_eq!(output.status, TaskStatus::TimedOut);
+        assert_eq!(output.stdout, b"started\n");
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_interrupt() {
+        let processes = Arc::new(Processes::new(Duration::from_millis(200)));
+        let running = Arc::clone(&processes);
+        let handle = tokio::spawn(async move {
+            running
+                .run(
+                    &sh("trap 'echo stopping; exit 1' INT; sleep 10 & wait"),
+                    None,
+                    &[],
+                    None,
+                    |_, _| {},
+                )
+                .await
+        });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        processes.interrupt(Signal::Interrupt);
+
+        let output = handle.await.unwrap();
+        assert_eq!(output.status, TaskStatus::Interrupted);
+        assert_eq!(output.stdout, b"stopping\n");
+        assert!(processes.groups.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,273 @@
+use colored::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+
+/// How a task finished.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskStatus {
+    Success,
+    /// The task didn't run, because its `sources` haven't changed since it last succeeded and
+    /// its `artifacts` still exist. Counts as a success.
+    UpToDate,
+    Failed,
+    /// The task was killed because it ran longer than its `timeout` or the workflow's.
+    TimedOut,
+    /// The task was stopped because the run was interrupted (Ctrl-C or SIGTERM).
+    Interrupted,
+    /// The task didn't run, because one of its dependencies failed, timed out, was interrupted
+    /// or was skipped itself.
+    Skipped,
+    /// The task didn't run, because its `when` condition (or that of a task it depends on) is
+    /// false. Unlike `Skipped`, this doesn't make the run fail.
+    ConditionFalse,
+    /// The task didn't run, because a dependency finished without failing but not with the
+    /// required status, like a `{"build" = "failure"}` dependency on a build that succeeded.
+    /// Unlike `Skipped`, this doesn't make the run fail.
+    NotNeeded,
+    /// The task was never started, because the run stopped early (after a failure with
+    /// `on_failure = "stop"`, or because it was interrupted).
+    NotRun,
+}
+
+/// The exit code of `xt run`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExitStatus {
+    /// Every task succeeded, or was up to date, had a false `when` condition or wasn't needed
+    /// (a dependency that didn't fail, but didn't finish with the required status either).
+    Success = 0,
+    /// At least one task failed, timed out, or was skipped or not run because of a failure.
+    TaskFailed = 1,
+    /// The workflow could not be read, is invalid or requires a different XTomate version.
+    InvalidWorkflow = 2,
+    /// A plugin could not be installed or loaded.
+    PluginError = 3,
+    /// The run was interrupted with Ctrl-C or SIGTERM.
+    Interrupted = 130,
+}
+
+/// One run of a task's command.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attempt {
+    pub status: TaskStatus,
+    /// The command's exit code, if it exited on its own.
+    pub exit_code: Option<i32>,
+    pub duration: Duration,
+}
+
+#[derive(Debug)]
+pub struct TaskResult {
+    pub name: String,
+    pub status: TaskStatus,
+    /// Every attempt at running the task's command (none for plugins and tasks that didn't
+    /// run).
+    pub attempts: Vec<Attempt>,
+    /// How long the task ran, from starting its command (or plugin) to its last attempt
+    /// finishing, including any retry delays. `None` if it didn't run.
+    pub duration: Option<Duration>,
+}
+
+/// The outcome of `Runner::run_all`: the status of every planned task, in the order they were
+/// planned in.
+#[derive(Debug)]
+pub struct RunResult {
+    pub tasks: Vec<TaskResult>,
+    pub interrupted: bool,
+}
+
+impl TaskStatus {
+    /// The status as it is written in dependencies and `when` conditions.
+    pub fn name(&self) -> &'static str {
+        match self {
+            TaskStatus::Success => "success",
+            TaskStatus::UpToDate => "up_to_date",
+            TaskStatus::Failed => "failure",
+            TaskStatus::TimedOut => "timeout",
+            TaskStatus::Interrupted => "interrupted",
+            TaskStatus::Skipped => "skipped",
+            TaskStatus::ConditionFalse => "condition_false",
+            TaskStatus::NotNeeded => "not_needed",
+            TaskStatus::NotRun => "not_run",
+        }
+    }
+
+    /// Whether this status satisfies a `{"task" = "status"}` dependency. A timeout counts as
+    /// a failure, and being up to date as a success.
+    pub fn satisfies(&self, required: &str) -> bool {
+        match required {
+            "success" => matches!(self, TaskStatus::Success | TaskStatus::UpToDate),
+            "up_to_date" => *self == TaskStatus::UpToDate,
+            "failure" | "fail" => matches!(self, TaskStatus::Failed | TaskStatus::TimedOut),
+            "timeout" => *self == TaskStatus::TimedOut,
+            "skipped" => matches!(
+                self,
+                TaskStatus::Skipped | TaskStatus::ConditionFalse | TaskStatus::NotNeeded
+            ),
+            "condition_false" => *self == TaskStatus::ConditionFalse,
+            "not_needed" => *self == TaskStatus::NotNeeded,
+            "any" => true,
+            _ => false,
+        }
+    }
+}
+
+impl RunResult {
+    pub fn success(&self) -> bool {
+        self.tasks.iter().all(|t| {
+            matches!(
+                t.status,
+                TaskStatus::Success
+                    | TaskStatus::UpToDate
+                    | TaskStatus::ConditionFalse
+                    | TaskStatus::NotNeeded
+            )
+        })
+    }
+
+    pub fn exit_status(&self) -> ExitStatus {
+        if self.interrupted {
+            ExitStatus::Interrupted
+        } else if self.success() {
+            ExitStatus::Success
+        } else {
+            ExitStatus::TaskFailed
+        }
+    }
+
+    fn count(&self, status: TaskStatus) -> usize {
+        self.tasks.iter().filter(|t| t.status == status).count()
+    }
+}
+
+impl ExitStatus {
+    pub fn code(self) -> i32 {
+        self as i32
+    }
+}
+
+impl fmt::Display for TaskStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TaskStatus::Success => write!(f, "success"),
+            TaskStatus::UpToDate => write!(f, "up to date"),
+            TaskStatus::Failed => write!(f, "failed"),
+            TaskStatus::TimedOut => write!(f, "timed out"),
+            TaskStatus::Interrupted => write!(f, "interrupted"),
+            TaskStatus::Skipped => write!(f, "skipped"),
+            TaskStatus::ConditionFalse => write!(f, "condition false"),
+            TaskStatus::NotNeeded => write!(f, "not needed"),
+            TaskStatus::NotRun => write!(f, "not run"),
+        }
+    }
+}
+
+impl fmt::Display for RunResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self.tasks.iter().map(|t| t.name.len()).max().unwrap_or(0);
+        writeln!(f, "{}", "Summary:".bold())?;
+        for task in self.tasks.iter() {
+            let status = match task.status {
+                TaskStatus::Success | TaskStatus::UpToDate => task.status.to_string().green(),
+                TaskStatus::Failed | TaskStatus::TimedOut => task.status.to_string().red(),
+                TaskStatus::Interrupted | TaskStatus::Skipped | TaskStatus::NotRun => {
+                    task.status.to_string().yellow()
+                }
+                TaskStatus::ConditionFalse | TaskStatus::NotNeeded => {
+                    task.status.to_string().dimmed()
+                }
+            };
+            write!(f, "  {:width$}  {}", task.name, status, width = width)?;
+            if task.attempts.len() > 1 {
+                write!(f, " (after {} attempts)", task.attempts.len())?;
+            }
+            writeln!(f)?;
+        }
+        write!(
+            f,
+            "{} task(s): {} succeeded, {} failed",
+            self.tasks.len(),
+            self.count(TaskStatus::Success),
+            self.count(TaskStatus::Failed),
+        )?;
+        for status in [
+            TaskStatus::UpToDate,
+            TaskStatus::TimedOut,
+            TaskStatus::Interrupted,
+            TaskStatus::Skipped,
+            TaskStatus::ConditionFalse,
+            TaskStatus::NotNeeded,
+            TaskStatus::NotRun,
+        ] {
+            match self.count(status) {
+                0 => {}
+                count => write!(f, ", {} {}", count, status)?,
+            }
+        }
+        if self.interrupted {
+            write!(f, " (run interrupted)")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_satisfies() {
+        assert!(TaskStatus::Success.satisfies("success"));
+        assert!(!TaskStatus::Success.satisfies("failure"));
+        assert!(TaskStatus::Failed.satisfies("fail"));
+        assert!(TaskStatus::TimedOut.satisfies("failure"));
+        assert!(TaskStatus::TimedOut.satisfies("timeout"));
+        assert!(!TaskStatus::Failed.satisfies("timeout"));
+        assert!(TaskStatus::Skipped.satisfies("any"));
+        assert!(TaskStatus::NotNeeded.satisfies("skipped"));
+        assert!(TaskStatus::NotNeeded.satisfies("not_needed"));
+        assert!(!TaskStatus::Skipped.satisfies("not_needed"));
+        assert!(TaskStatus::Skipped.satisfies("skipped"));
+        assert!(!TaskStatus::Skipped.satisfies("failure"));
+        assert!(TaskStatus::ConditionFalse.satisfies("skipped"));
+        assert!(TaskStatus::ConditionFalse.satisfies("condition_false"));
+        assert!(!TaskStatus::Skipped.satisfies("condition_false"));
+        assert!(TaskStatus::UpToDate.satisfies("success"));
+        assert!(TaskStatus::UpToDate.satisfies("up_to_date"));
+        assert!(!TaskStatus::Success.satisfies("up_to_date"));
+        assert!(!TaskStatus::Success.satisfies("unknown"));
+    }
+
+    #[test]
+    fn test_exit_status() {
+        let mut result = RunResult {
+            tasks: vec![TaskResult {
+                name: "build".to_string(),
+                status: TaskStatus::Success,
+                attempts: vec![],
+                duration: Some(Duration::from_secs(1)),
+            }],
+            interrupted: false,
+        };
+        assert_eq!(result.exit_status().code(), 0);
+
+        result.tasks.push(TaskResult {
+            name: "deploy".to_string(),
+            status: TaskStatus::ConditionFalse,
+            attempts: vec![],
+            duration: None,
+        });
+        assert_eq!(result.exit_status(), ExitStatus::Success);
+
+        result.tasks.push(TaskResult {
+            name: "test".to_string(),
+            status: TaskStatus::Skipped,
+            attempts: vec![],
+            duration: None,
+        });
+        assert_eq!(result.exit_status(), ExitStatus::TaskFailed);
+
+        result.interrupted = true;
+        assert_eq!(result.exit_status().code(), 130);
+    }
+}
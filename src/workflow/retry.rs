@@ -0,0 +1,183 @@
+use regex::Regex;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+use super::duration::DurationValue;
+use super::structure::{Backoff, RetryOptions};
+
+/// When and how often a failed command is run again, with every duration and pattern in its
+/// `RetryOptions` parsed.
+#[derive(Debug, Clone, Default)]
+pub struct RetryPolicy {
+    pub retries: usize,
+    pub delay: Duration,
+    pub backoff: Backoff,
+    pub max_delay: Option<Duration>,
+    pub jitter: Duration,
+    pub exit_codes: Option<Vec<i32>>,
+    pub no_exit_codes: Vec<i32>,
+    pub stderr: Option<Regex>,
+}
+
+impl RetryPolicy {
+    pub fn new(options: &RetryOptions) -> Result<Self, String> {
+        let duration = |name: &str, value: &Option<DurationValue>| {
+            value
+                .as_ref()
+                .map(DurationValue::to_duration)
+                .transpose()
+                .map_err(|e| format!("{}: {}", name, e))
+        };
+        Ok(RetryPolicy {
+            retries: options.retry.unwrap_or(0),
+            delay: duration("retry_delay", &options.retry_delay)?.unwrap_or_default(),
+            backoff: options.retry_backoff.unwrap_or_default(),
+            max_delay: duration("retry_max_delay", &options.retry_max_delay)?,
+            jitter: duration("retry_jitter", &options.retry_jitter)?.unwrap_or_default(),
+            exit_codes: options.retry_exit_codes.clone(),
+            no_exit_codes: options.no_retry_exit_codes.clone().unwrap_or_default(),
+            stderr: options
+                .retry_stderr
+                .as_deref()
+                .map(Regex::new)
+                .transpose()
+                .map_err(|e| format!("retry_stderr: {}", e))?,
+        })
+    }
+
+    /// Whether a failed attempt that exited with `exit_code` and wrote `stderr` should be
+    /// retried. Attempts that were killed (timed out) have no exit code.
+    pub fn should_retry(&self, exit_code: Option<i32>, stderr: &str) -> bool {
+        if exit_code.is_some_and(|code| self.no_exit_codes.contains(&code)) {
+            return false;
+        }
+        if self.exit_codes.is_none() && self.stderr.is_none() {
+            return true;
+        }
+        let code_matches = self
+            .exit_codes
+            .as_ref()
+            .is_some_and(|codes| exit_code.is_some_and(|code| codes.contains(&code)));
+        let stderr_matches = self
+            .stderr
+            .as_ref()
+            .is_some_and(|pattern| pattern.is_match(stderr));
+        code_matches || stderr_matches
+    }
+
+    /// How long to wait before retry number `retry` (starting at 1), without jitter.
+    pub fn base_delay(&self, retry: usize) -> Duration {
+        let retry = u32::try_from(retry.max(1)).unwrap_or(u32::MAX);
+        let delay = match self.backoff {
+            Backoff::Fixed => self.delay,
+            Backoff::Linear => self.delay.saturating_mul(retry),
+            Backoff::Exponential => self.delay.saturating_mul(2u32.saturating_pow(retry - 1)),
+        };
+        match self.max_delay {
+            Some(max_delay) => delay.min(max_delay),
+            None => delay,
+        }
+    }
+
+    /// How long to wait before retry number `retry`, with a random part of the jitter added.
+    pub fn delay(&self, retry: usize) -> Duration {
+        let delay = self.base_delay(retry);
+        if self.jitter.is_zero() {
+            return delay;
+        }
+        let random = RandomState::new().build_hasher().finish();
+        delay + self.jitter.mul_f64(random as f64 / u64::MAX as f64)
+    }
+}
+
+impl fmt::Display for RetryPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} time(s), {:?} apart", self.retries, self.delay)?;
+        match self.backoff {
+            Backoff::Fixed => {}
+            Backoff::Linear => write!(f, ", growing linearly")?,
+            Backoff::Exponential => write!(f, ", doubling every time")?,
+        }
+        if let Some(max_delay) = self.max_delay {
+            write!(f, " up to {:?}", max_delay)?;
+        }
+        if !self.jitter.is_zero() {
+            write!(f, ", plus up to {:?} of jitter", self.jitter)?;
+        }
+        if let Some(codes) = &self.exit_codes {
+            write!(f, ", on exit codes {:?}", codes)?;
+        }
+        if let Some(pattern) = &self.stderr {
+            write!(f, ", when stderr matches `{}`", pattern)?;
+        }
+        if !self.no_exit_codes.is_empty() {
+            write!(f, ", never on exit codes {:?}", self.no_exit_codes)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delay() {
+        let mut policy = RetryPolicy {
+            delay: Duration::from_millis(500),
+            ..Default::default()
+        };
+        assert_eq!(policy.delay(3), Duration::from_millis(500));
+
+        policy.backoff = Backoff::Linear;
+        assert_eq!(policy.delay(3), Duration::from_millis(1500));
+
+        policy.backoff = Backoff::Exponential;
+        assert_eq!(policy.delay(1), Duration::from_millis(500));
+        assert_eq!(policy.delay(4), Duration::from_secs(4));
+
+        policy.max_delay = Some(Duration::from_secs(3));
+        assert_eq!(policy.delay(4), Duration::from_secs(3));
+        assert_eq!(policy.delay(100), Duration::from_secs(3));
+
+        policy.jitter = Duration::from_secs(1);
+        let delay = policy.delay(4);
+        assert!(delay >= Duration::from_secs(3) && delay <= Duration::from_secs(4));
+    }
+
+    #[test]
+    fn test_should_retry() {
+        let options = RetryOptions {
+            retry: Some(3),
+            retry_delay: Some(DurationValue::Text("100ms".to_string())),
+            no_retry_exit_codes: Some(vec![2]),
+            ..Default::default()
+        };
+        let policy = RetryPolicy::new(&options).unwrap();
+        assert_eq!(policy.delay, Duration::from_millis(100));
+        assert!(policy.should_retry(Some(1), ""));
+        assert!(policy.should_retry(None, ""));
+        assert!(!policy.should_retry(Some(2), ""));
+
+        let options = RetryOptions {
+            retry_exit_codes: Some(vec![75]),
+            retry_stderr: Some("(?i)connection reset".to_string()),
+            ..options
+        };
+        let policy = RetryPolicy::new(&options).unwrap();
+        assert!(policy.should_retry(Some(75), ""));
+        assert!(policy.should_retry(Some(1), "error: Connection reset by peer"));
+        assert!(!policy.should_retry(Some(1), "error: file not found"));
+        assert!(!policy.should_retry(None, ""));
+
+        let options = RetryOptions {
+            retry_stderr: Some("(".to_string()),
+            ..Default::default()
+        };
+        assert!(RetryPolicy::new(&options)
+            .unwrap_err()
+            .starts_with("retry_stderr:"));
+    }
+}
@@ -1,25 +1,72 @@
+use colored::*;
 use libloading::{Library, Symbol};
 use semver::VersionReq;
 use serde_json;
 use std::ffi::CString;
+use std::fmt::Write;
 use std::os::raw::c_char;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use std::{
-    collections::{HashMap, HashSet, VecDeque},
-    sync::{Arc, Mutex},
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, OnceLock,
+    },
 };
-use toml::{Table, Value};
+use tokio::sync::{Notify, Semaphore, SemaphorePermit};
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+use toml::Table;
 
+use crate::error::{Cause, Error};
 use crate::plugins;
 
-use super::placeholders::Context;
-use super::structure::{Dependency, WorkFlow};
+use super::condition::Expr;
+use super::duration::parse_duration;
+use super::fingerprint::{self, Fingerprint, FingerprintStore};
+use super::graph::TaskGraph;
+use super::params;
+use super::placeholders::{output_key, Context};
+use super::process::{Processes, Signal, Stream, DEFAULT_GRACE_PERIOD};
+use super::result::{Attempt, RunResult, TaskResult, TaskStatus};
+use super::retry::RetryPolicy;
+use super::runs::RunLog;
+use super::structure::{
+    Dependency, FailurePolicy, OutputMode, OutputSource, OutputStream, RetryOptions, Shell, Task,
+    WorkFlow,
+};
+use super::validate::{Issue, Scope};
 
 pub struct Runner {
     workflow: WorkFlow,
     tasks: HashMap<String, RunnerTask>,
     order: Vec<Vec<String>>,
+    dependencies: HashMap<String, Vec<String>>,
     plugin_manager: plugins::manager::PluginManager,
     plugins: Vec<RunnerPlugin>,
+    targets: Vec<String>,
+    jobs: Option<usize>,
+    parallel: Option<Semaphore>,
+    pools: HashMap<String, Semaphore>,
+    deadline: OnceLock<Instant>,
+    processes: Processes,
+    finishing: AtomicBool,
+    keep_going: bool,
+    failed: AtomicBool,
+    output: Option<OutputMode>,
+    log: Option<RunLog>,
+    /// The outputs of the tasks that have succeeded, by their `output_key`.
+    outputs: Mutex<HashMap<String, String>>,
+    /// The directory `cwd` is relative to, usually the one the workflow file is in.
+    base_dir: Option<PathBuf>,
+    /// The parameter values given for the run, in the order they were given.
+    param_values: Vec<(String, String)>,
+    /// The value of every parameter once they are resolved by `plan`.
+    params: Vec<(String, String)>,
+    /// Where the fingerprints of tasks with `sources` are kept between runs.
+    fingerprints: Option<FingerprintStore>,
+    force: bool,
 }
 
 struct RunnerPlugin {
@@ -27,8 +74,63 @@ struct RunnerPlugin {
     plugin: Library,
 }
 
+/// What a task does once all of its placeholders are resolved.
+enum Action {
+    Command(Box<ResolvedCommand>),
+    Plugin { name: String, config: String },
+}
+
+struct ResolvedCommand {
+    program: Program,
+    cwd: Option<PathBuf>,
+    env: Vec<(String, String)>,
+    retry: RetryPolicy,
+    timeout: Option<Duration>,
+}
+
+/// How a command is started.
+enum Program {
+    /// A command line, run by a shell.
+    Shell { shell: Shell, command: String },
+    /// A program and its arguments, run without a shell so they aren't split again.
+    Exec(Vec<String>),
+}
+
+impl Program {
+    fn argv(&self) -> Vec<String> {
+        match self {
+            Program::Shell { shell, command } => shell.argv(command),
+            Program::Exec(argv) => argv.clone(),
+        }
+    }
+}
+
+impl std::fmt::Display for Program {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Program::Shell { command, .. } => write!(f, "{}", command.trim_end()),
+            Program::Exec(argv) => write!(f, "{}", quote_args(argv)),
+        }
+    }
+}
+
+/// What a command printed on its last attempt, and how every attempt went.
+#[derive(Default)]
+struct CommandOutput {
+    stdout: String,
+    stderr: String,
+    attempts: Vec<Attempt>,
+}
+
 struct RunnerTask {
-    success: Arc<Mutex<Option<bool>>>,
+    status: Arc<Mutex<Option<TaskStatus>>>,
+    /// Set once something (the scheduler, a dependent or a hook) has started running the
+    /// task, so nothing else starts it again.
+    started: AtomicBool,
+    /// Notified whenever the task's status is set.
+    finished: Notify,
+    attempts: Mutex<Vec<Attempt>>,
+    duration: Mutex<Option<Duration>>,
 }
 
 impl Runner {
@@ -37,47 +139,134 @@ impl Runner {
             workflow,
             tasks: HashMap::new(),
             order: vec![],
+            dependencies: HashMap::new(),
             plugin_manager,
             plugins: vec![],
+            targets: vec![],
+            jobs: None,
+            parallel: None,
+            pools: HashMap::new(),
+            deadline: OnceLock::new(),
+            processes: Processes::new(DEFAULT_GRACE_PERIOD),
+            finishing: AtomicBool::new(false),
+            keep_going: false,
+            failed: AtomicBool::new(false),
+            output: None,
+            log: None,
+            outputs: Mutex::new(HashMap::new()),
+            base_dir: None,
+            param_values: vec![],
+            params: vec![],
+            fingerprints: None,
+            force: false,
         }
     }
 
-    pub fn load(&mut self) {
-        let version_req = VersionReq::parse(&self.workflow.version).unwrap();
+    /// Plans which tasks run in which stage, without loading any plugins. Fails if the
+    /// workflow requires a different XTomate version or its tasks can't be ordered.
+    pub fn plan(&mut self) -> Result<(), Error> {
+        let version_req = VersionReq::parse(&self.workflow.version).map_err(|e| {
+            self.invalid(format!(
+                "invalid version requirement `{}`: {}",
+                self.workflow.version, e
+            ))
+        })?;
         if !version_req.matches(&semver::Version::parse(env!("CARGO_PKG_VERSION")).unwrap()) {
-            panic!(
-                "Workflow version mismatch: required {}, found {}",
+            return Err(self.invalid(format!(
+                "requires XTomate {}, but this is version {}",
                 self.workflow.version,
                 env!("CARGO_PKG_VERSION")
-            );
+            )));
+        }
+
+        self.params = params::resolve(
+            self.workflow.get_params().unwrap_or(&HashMap::new()),
+            &self.param_values,
+        )
+        .map_err(|issues| Error::Validation {
+            workflow: self.workflow.name.clone(),
+            issues,
+        })?;
+
+        if let Some(grace_period) = &self.workflow.grace_period {
+            let grace_period = parse_duration(grace_period)
+                .map_err(|e| self.invalid(format!("grace_period: {}", e)))?;
+            self.processes = Processes::new(grace_period);
+        }
+        self.parallel = self.jobs.or(self.workflow.max_parallel).map(Semaphore::new);
+        self.pools = self
+            .workflow
+            .get_resources()
+            .into_iter()
+            .flatten()
+            .map(|(pool, capacity)| (pool.clone(), Semaphore::new(*capacity)))
+            .collect();
+
+        let tasks = self.workflow.get_tasks();
+        if tasks.is_empty() {
+            return Ok(());
+        }
+
+        for (name, _) in tasks.iter() {
+            let runnertask = RunnerTask {
+                status: Arc::new(Mutex::new(None)),
+                started: AtomicBool::new(false),
+                finished: Notify::new(),
+                attempts: Mutex::new(vec![]),
+                duration: Mutex::new(None),
+            };
+
+            self.tasks.insert(name.clone(), runnertask);
+        }
+
+        self.determine_order().map_err(|e| self.invalid(e))
+    }
+
+    fn invalid(&self, message: String) -> Error {
+        Error::Validation {
+            workflow: self.workflow.name.clone(),
+            issues: vec![Issue {
+                scope: Scope::Workflow,
+                message,
+            }],
         }
+    }
 
+    /// Installs (if needed) and initializes every plugin the workflow uses.
+    pub fn load_plugins(&mut self) -> Result<(), Error> {
         let plugins = self.workflow.get_plugins();
         if let Some(plugins) = plugins {
             for plugin in plugins {
+                self.plugin_manager.verify_plugin(
+                    plugin.name.clone(),
+                    plugin.source.clone(),
+                    plugin.version.clone(),
+                )?;
+
+                let path = match self.plugin_manager.get_plugin(&plugin.name) {
+                    Some(installed) => installed.get_install_path().clone(),
+                    None => self.plugin_manager.library_path(&plugin.name),
+                };
+                let load_error = |cause: Cause| Error::PluginLoad {
+                    plugin: plugin.name.clone(),
+                    path: path.clone(),
+                    cause,
+                };
+
                 unsafe {
-                    self.plugin_manager
-                        .verify_plugin(
-                            plugin.name.clone(),
-                            plugin.source.clone(),
-                            plugin.version.clone(),
-                        )
-                        .unwrap();
-
-                    let lib_path = self
-                        .plugin_manager
-                        .get_plugin(plugin.name.as_str())
-                        .unwrap();
-                    let lib =
-                        Library::new(lib_path.get_install_path()).expect("Failed to load plugin");
+                    let lib = Library::new(&path).map_err(|e| load_error(e.into()))?;
 
                     let initialize: Symbol<unsafe extern "C" fn(*const c_char) -> i32> =
-                        lib.get(b"initialize").unwrap();
+                        lib.get(b"initialize").map_err(|e| load_error(e.into()))?;
 
                     let config_json = serde_json::to_string(&plugin.get_config()).unwrap();
-                    let config_cstr = CString::new(config_json).unwrap();
+                    let config_cstr =
+                        CString::new(config_json).map_err(|e| load_error(e.into()))?;
 
-                    initialize(config_cstr.as_ptr());
+                    let code = initialize(config_cstr.as_ptr());
+                    if code != 0 {
+                        return Err(load_error(format!("`initialize` returned {}", code).into()));
+                    }
 
                     self.plugins.push(RunnerPlugin {
                         name: plugin.name.clone(),
@@ -86,755 +275,1335 @@ impl Runner {
                 }
             }
         }
-
-        let tasks = self.workflow.get_tasks();
-        if tasks.is_empty() {
-            return;
-        }
-
-        for (name, _) in tasks.iter() {
-            let runnertask = RunnerTask {
-                success: Arc::new(Mutex::new(None)),
-            };
-
-            self.tasks.insert(name.clone(), runnertask);
-        }
-
-        self.determine_order().expect("Failed to determine order");
+        Ok(())
     }
 
     pub fn teardown(&self) {
         for plugin in self.plugins.iter() {
             unsafe {
-                let teardown: Symbol<unsafe extern "C" fn() -> i32> =
-                    plugin.plugin.get(b"teardown").unwrap();
-                teardown();
+                if let Ok(teardown) = plugin
+                    .plugin
+                    .get::<unsafe extern "C" fn() -> i32>(b"teardown")
+                {
+                    teardown();
+                }
             }
         }
     }
 
+    /// Selects the tasks to run instead of every runnable task in the workflow. The selected
+    /// tasks run together with all of their transitive dependencies, even ones marked
+    /// `run = false`.
+    pub fn set_targets(&mut self, targets: Vec<String>) {
+        self.targets = targets;
+    }
+
+    /// Stops the run: no more tasks are started, `signal` is forwarded to the running ones,
+    /// and those still running after the grace period are killed. The workflow's `on_finish`
+    /// hooks and plugin teardown still run.
+    pub fn interrupt(&self, signal: Signal) {
+        self.processes.interrupt(signal);
+    }
+
+    /// Kills every running command immediately.
+    pub fn kill(&self) {
+        self.processes.kill_all();
+    }
+
+    fn interrupted(&self) -> bool {
+        self.processes.interrupted().is_some() && !self.finishing.load(Ordering::SeqCst)
+    }
+
+    /// Keeps running independent tasks after a failure, even if the workflow sets
+    /// `on_failure = "stop"`.
+    pub fn set_keep_going(&mut self, keep_going: bool) {
+        self.keep_going = keep_going;
+    }
+
+    /// Sets how command output is printed, overriding the workflow's `output`.
+    pub fn set_output(&mut self, output: OutputMode) {
+        self.output = Some(output);
+    }
+
+    /// Records the run, and the output of every task, in `log`.
+    pub fn set_log(&mut self, log: RunLog) {
+        self.log = Some(log);
+    }
+
+    /// Runs commands with a relative `cwd` in `dir` instead of the current directory.
+    pub fn set_base_dir(&mut self, dir: PathBuf) {
+        self.base_dir = Some(dir);
+    }
+
+    /// Sets workflow parameters. A value given later overrides one given earlier for the
+    /// same parameter.
+    pub fn set_params(&mut self, values: Vec<(String, String)>) {
+        self.param_values = values;
+    }
+
+    /// Keeps the fingerprints of tasks with `sources` in `dir`, so they can be skipped when
+    /// nothing they depend on has changed since they last succeeded.
+    pub fn set_fingerprints_dir(&mut self, dir: PathBuf) {
+        self.fingerprints = Some(FingerprintStore::new(&dir, &self.workflow.name));
+    }
+
+    /// Runs tasks with `sources` even if they are up to date.
+    pub fn set_force(&mut self, force: bool) {
+        self.force = force;
+    }
+
+    pub fn log(&self) -> Option<&RunLog> {
+        self.log.as_ref()
+    }
+
+    /// Whether the scheduler should stop starting new tasks.
+    fn stopping(&self) -> bool {
+        self.interrupted() || self.failed.load(Ordering::SeqCst)
+    }
+
+    /// Limits how many tasks run at the same time, overriding the workflow's `max_parallel`.
+    pub fn set_jobs(&mut self, jobs: usize) {
+        self.jobs = Some(jobs);
+    }
+
     fn determine_order(&mut self) -> Result<(), String> {
         let tasks = self.workflow.get_tasks();
-        let mut graph: HashMap<String, HashSet<String>> = HashMap::new();
-        let mut in_degree: HashMap<String, usize> = HashMap::new();
-        let mut non_runnable_tasks = HashSet::new();
+        let mut pending: Vec<String> = if self.targets.is_empty() {
+            tasks
+                .iter()
+                .filter(|(_, task)| task.run.unwrap_or(true))
+                .map(|(name, _)| name.clone())
+                .collect()
+        } else {
+            self.targets.clone()
+        };
 
-        for (task, dependencies) in tasks {
-            if let Some(run) = &dependencies.run {
-                if !run {
-                    non_runnable_tasks.insert(task.clone());
-                }
+        let task_graph = TaskGraph::new(&self.workflow);
+        let mut dependencies: HashMap<String, Vec<String>> = HashMap::new();
+        while let Some(task) = pending.pop() {
+            if dependencies.contains_key(&task) {
+                continue;
             }
+            let task_dependencies = task_graph
+                .dependencies(&task)
+                .ok_or_else(|| format!("Unknown task: {}", task))?;
+            pending.extend(task_dependencies.iter().cloned());
+            dependencies.insert(task, task_dependencies);
+        }
 
-            graph.entry(task.clone()).or_default();
-            in_degree.entry(task.clone()).or_insert(0);
+        let mut graph: HashMap<String, HashSet<String>> = HashMap::new();
+        let mut in_degree: HashMap<String, usize> = HashMap::new();
 
-            for dependency in dependencies.get_dependencies().unwrap_or(&vec![]) {
-                match &dependency {
-                    Dependency::Simple(dependency) => {
-                        graph
-                            .entry(dependency.clone())
-                            .or_default()
-                            .insert(task.clone());
-                        *in_degree.entry(task.clone()).or_insert(0) += 1;
-                    }
-                    Dependency::Status(dep) => {
-                        let dependency = dep.keys().next().unwrap();
-                        graph
-                            .entry(dependency.clone())
-                            .or_default()
-                            .insert(task.clone());
-                        *in_degree.entry(task.clone()).or_insert(0) += 1;
-                    }
+        for (task, task_dependencies) in dependencies.iter() {
+            in_degree.entry(task.clone()).or_insert(0);
+            for dependency in task_dependencies {
+                if graph
+                    .entry(dependency.clone())
+                    .or_default()
+                    .insert(task.clone())
+                {
+                    *in_degree.entry(task.clone()).or_insert(0) += 1;
                 }
             }
         }
 
-        let mut queue: VecDeque<String> = in_degree
+        let mut stage: Vec<String> = in_degree
             .iter()
-            .filter(|&(task, &deg)| deg == 0 && !non_runnable_tasks.contains(task))
+            .filter(|&(_, &deg)| deg == 0)
             .map(|(task, _)| task.clone())
             .collect();
 
         let mut stages: Vec<Vec<String>> = vec![];
-        let mut visited: HashSet<String> = HashSet::new();
-
-        while !queue.is_empty() {
-            let mut current_stage = vec![];
+        let mut visited = 0;
 
-            for _ in 0..queue.len() {
-                let task = queue.pop_front().unwrap();
-                current_stage.push(task.clone());
-                visited.insert(task.clone());
+        while !stage.is_empty() {
+            stage.sort();
+            let mut next_stage = vec![];
 
-                if let Some(dependents) = graph.get(&task) {
+            for task in stage.iter() {
+                visited += 1;
+                if let Some(dependents) = graph.get(task) {
                     for dependent in dependents {
                         if let Some(degree) = in_degree.get_mut(dependent) {
                             *degree -= 1;
-                            if *degree == 0 && !non_runnable_tasks.contains(dependent) {
-                                queue.push_back(dependent.clone());
+                            if *degree == 0 {
+                                next_stage.push(dependent.clone());
                             }
                         }
                     }
                 }
             }
 
-            if !current_stage.is_empty() {
-                stages.push(current_stage);
-            }
+            stages.push(stage);
+            stage = next_stage;
         }
 
-        let runnable_task_count = tasks
-            .keys()
-            .filter(|task| !non_runnable_tasks.contains(*task))
-            .count();
-
-        if visited.len() != runnable_task_count {
+        if visited != dependencies.len() {
             return Err("Cycle detected in task dependencies".to_string());
         }
 
         self.order = stages;
+        self.dependencies = dependencies;
         Ok(())
     }
 
     pub async fn run(&self, task_name: &str) {
         if let Some(task) = self.workflow.get_task(task_name) {
-            if let Some(dependencies) = task.get_dependencies() {
-                for dep in dependencies.iter() {
-                    match dep {
-                        Dependency::Simple(dependency) => {
-                            if self.needs_run(dependency) {
-                                Box::pin(self.run(dependency)).await;
-                            }
-                            if !self.check_dependency_status(dependency, "success") {
-                                panic!("Dependency failed: {}, terminating workflow!", dependency);
-                            }
-                        }
-                        Dependency::Status(dep) => {
-                            let dependency = dep.keys().next().unwrap();
-                            let required_status = dep.get(dependency).unwrap().as_str().unwrap();
-                            if self.needs_run(dependency) {
-                                Box::pin(self.run(dependency)).await;
-                            }
-                            if !self.check_dependency_status(dependency, required_status) {
-                                panic!("Dependency did not satisfy state {}: {}, terminating workflow!", required_status, dependency);
-                            }
-                        }
-                    }
+            let template_dependencies = task
+                .template
+                .as_ref()
+                .and_then(|t| self.workflow.get_template(t))
+                .and_then(|t| t.get_dependencies());
+            // Tasks whose outputs are used have to succeed first
+            let mut producers: Vec<Dependency> = vec![];
+            for (producer, _) in self.workflow.output_references(task_name) {
+                let producer = Dependency::Simple(producer);
+                if !producers.contains(&producer) {
+                    producers.push(producer);
+                }
+            }
+            // Tasks used by `when` conditions only have to finish
+            let conditions: Vec<Dependency> = self
+                .workflow
+                .condition_references(task_name)
+                .into_iter()
+                .map(|task| {
+                    let mut table = Table::new();
+                    table.insert(task, toml::Value::String("any".to_string()));
+                    Dependency::Status(table)
+                })
+                .collect();
+            let dependencies = async {
+                self.run_dependencies(task.get_dependencies()).await?;
+                self.run_dependencies(template_dependencies).await?;
+                self.run_dependencies(Some(&producers)).await?;
+                self.run_dependencies(Some(&conditions)).await
+            };
+            if let Err(status) = dependencies.await {
+                self.set_status(task_name, status);
+                return;
+            }
+            match self.check_conditions(task_name) {
+                Ok(true) => self.execute_task(task_name).await,
+                Ok(false) => {
+                    eprintln!("Skipping `{}`: its `when` condition is false", task_name);
+                    self.set_status(task_name, TaskStatus::ConditionFalse);
+                }
+                Err(e) => {
+                    report(&e);
+                    self.set_status(task_name, TaskStatus::Failed);
                 }
             }
-
-            self.execute_task(task_name).await;
         }
     }
 
-    async fn execute_task(&self, task_name: &str) {
-        let task = self.workflow.get_task(task_name).unwrap();
-        let success;
-
-        let mut context = Context::new();
-
-        context.set("XTOMATE_WORKFLOW".to_string(), self.workflow.name.clone());
-        context.set("XTOMATE_TASK".to_string(), task_name.to_string());
-
-        if let Some(on_start) = task.get_on_start() {
-            for finish in on_start.iter() {
-                match finish {
-                    Dependency::Simple(task) => match parse_dependency(task) {
-                        ("task", task) => {
-                            Box::pin(self.run(task)).await;
-                        }
-                        ("template", template) => {
-                            let _ = self.execute_template(
-                                task_name,
-                                template,
-                                &Table::new(),
-                                &mut context,
-                                &mut String::new(), // TODO: Optimize this
-                                &mut String::new(),
-                            );
-                        }
-                        ("plugin", plugin) => {
-                            let _ = self
-                                .execute_plugin(plugin, &Table::new(), &mut context)
-                                .await;
-                        }
-                        _ => panic!("Invalid dependency: {}", task),
-                    },
-                    Dependency::Status(dep) => match parse_dependency(dep.keys().next().unwrap()) {
-                        ("task", task) => {
-                            Box::pin(self.run(task)).await;
-                        }
-                        ("template", template) => {
-                            let _ = self.execute_template(
-                                task_name,
-                                template,
-                                &Table::new(),
-                                &mut context,
-                                &mut String::new(),
-                                &mut String::new(),
-                            );
-                        }
-                        ("plugin", plugin) => {
-                            let _ = self
-                                .execute_plugin(
-                                    plugin,
-                                    &context.resolve_table(
-                                        dep.values()
-                                            .next()
-                                            .unwrap_or(&Value::Table(Table::new()))
-                                            .as_table()
-                                            .unwrap(),
-                                    ),
-                                    &mut context,
-                                )
-                                .await;
-                        }
-                        _ => panic!("Invalid dependency: {}", dep.keys().next().unwrap()),
-                    },
+    /// Runs the dependencies that haven't run yet. If one of them doesn't finish with the
+    /// status required of it, returns the status of the dependent: `ConditionFalse` if the
+    /// dependency didn't run because of its `when` condition, `NotNeeded` if it finished
+    /// without failing (or wasn't needed either), `Skipped` otherwise.
+    async fn run_dependencies(
+        &self,
+        dependencies: Option<&Vec<Dependency>>,
+    ) -> Result<(), TaskStatus> {
+        for dep in dependencies.into_iter().flatten() {
+            let (dependency, required_status) = match dep {
+                Dependency::Simple(dependency) => (dependency.as_str(), "success"),
+                Dependency::Status(dep) => {
+                    let dependency = dep.keys().next().unwrap();
+                    (
+                        dependency.as_str(),
+                        dep.get(dependency).unwrap().as_str().unwrap(),
+                    )
                 }
+            };
+            Box::pin(self.run_once(dependency)).await;
+            if !self.check_dependency_status(dependency, required_status) {
+                let status = match self.status(dependency) {
+                    Some(TaskStatus::ConditionFalse) => return Err(TaskStatus::ConditionFalse),
+                    Some(TaskStatus::Success | TaskStatus::UpToDate | TaskStatus::NotNeeded) => {
+                        TaskStatus::NotNeeded
+                    }
+                    _ => TaskStatus::Skipped,
+                };
+                eprintln!(
+                    "Dependency `{}` did not finish with status `{}`",
+                    dependency, required_status
+                );
+                return Err(status);
             }
         }
+        Ok(())
+    }
 
-        let mut output = "".to_string();
-        let mut error = "".to_string();
-
-        if task.template.is_some() {
-            if let Some(config) = task.get_config() {
-                for (key, value) in config.iter() {
-                    context.set(key.clone(), value.as_str().unwrap().to_string());
-                }
+    /// Evaluates the `when` conditions of a task and its template, which are true if they
+    /// aren't set.
+    fn check_conditions(&self, task_name: &str) -> Result<bool, Error> {
+        let context = self.task_context(task_name);
+        let lookup = |name: &str| {
+            if let Some(variable) = name.strip_prefix("env.") {
+                return std::env::var(variable).ok();
             }
-            success = self
-                .execute_template(
-                    &task_name,
-                    task.template.clone().unwrap().as_str(),
-                    task.get_env().unwrap_or(&Table::new()),
-                    &mut context,
-                    &mut output,
-                    &mut error,
-                )
-                .await;
-        } else if task.command.is_some() {
-            success = self
-                .execute_command(
-                    &task_name,
-                    task.command.clone().unwrap().as_str(),
-                    task.get_env().unwrap_or(&Table::new()),
-                    task.retry.unwrap_or(0),
-                    task.retry_delay.unwrap_or(0),
-                    &mut context,
-                    &mut output,
-                    &mut error,
-                )
-                .await;
-        } else if task.plugin.is_some() {
-            success = self
-                .execute_plugin(
-                    task.plugin.clone().unwrap().as_str(),
-                    task.get_config().unwrap_or(&Table::new()),
-                    &mut context,
-                )
-                .await;
-        } else {
-            panic!("Task `{}` has no command, plugin, or template", task_name);
-        }
-
-        if !success {
-            if let Some(on_error) = task.get_on_error() {
-                context.set("XTOMATE_STDERR".to_string(), error.to_string());
-                context.set("XTOMATE_STDOUT".to_string(), output.to_string());
-                for finish in on_error.iter() {
-                    match finish {
-                        Dependency::Simple(task) => match parse_dependency(task) {
-                            ("task", task) => {
-                                Box::pin(self.run(task)).await;
-                            }
-                            ("template", template) => {
-                                let _ = self.execute_template(
-                                    task_name,
-                                    template,
-                                    &Table::new(),
-                                    &mut context,
-                                    &mut output,
-                                    &mut error,
-                                );
-                            }
-                            ("plugin", plugin) => {
-                                let _ = self
-                                    .execute_plugin(plugin, &Table::new(), &mut context)
-                                    .await;
-                            }
-                            _ => panic!("Invalid dependency: {}", task),
-                        },
-                        Dependency::Status(dep) => {
-                            match parse_dependency(dep.keys().next().unwrap()) {
-                                ("task", task) => {
-                                    Box::pin(self.run(task)).await;
-                                }
-                                ("template", template) => {
-                                    let _ = self.execute_template(
-                                        task_name,
-                                        template,
-                                        &Table::new(),
-                                        &mut context,
-                                        &mut output,
-                                        &mut error,
-                                    );
-                                }
-                                ("plugin", plugin) => {
-                                    let _ = self
-                                        .execute_plugin(
-                                            plugin,
-                                            &context.resolve_table(
-                                                dep.values()
-                                                    .next()
-                                                    .unwrap_or(&Value::Table(Table::new()))
-                                                    .as_table()
-                                                    .unwrap(),
-                                            ),
-                                            &mut context,
-                                        )
-                                        .await;
-                                }
-                                _ => panic!("Invalid dependency: {}", dep.keys().next().unwrap()),
-                            }
-                        }
-                    }
-                }
+            if let Some(status) = name
+                .strip_prefix("tasks.")
+                .and_then(|name| name.strip_suffix(".status"))
+            {
+                return Some(
+                    self.status(status)
+                        .unwrap_or(TaskStatus::NotRun)
+                        .name()
+                        .to_string(),
+                );
+            }
+            context.get(name).map(|value| value.to_string())
+        };
+        for condition in self.workflow.conditions(task_name) {
+            let expr = Expr::parse(condition).map_err(|e| Error::Task {
+                task: task_name.to_string(),
+                cause: format!("when: {}", e).into(),
+            })?;
+            if !expr.evaluate(&lookup) {
+                return Ok(false);
             }
         }
+        Ok(true)
+    }
 
-        if let Some(runner_task) = self.tasks.get(task_name) {
-            *runner_task.success.lock().expect("Failed to lock mutex") = Some(success);
+    /// The placeholders every task and hook can use: the workflow's name and parameters.
+    fn workflow_context(&self) -> Context {
+        let mut context = Context::new();
+        context.set("XTOMATE_WORKFLOW".to_string(), self.workflow.name.clone());
+        for (name, value) in self.params.iter() {
+            context.set(format!("params.{}", name), value.clone());
         }
+        context
+    }
 
-        if let Some(on_finish) = task.get_on_finish() {
-            context.set("XTOMATE_STDERR".to_string(), error.to_string());
-            context.set("XTOMATE_STDOUT".to_string(), output.to_string());
-            for finish in on_finish.iter() {
-                match finish {
-                    Dependency::Simple(task) => match parse_dependency(task) {
-                        ("task", task) => {
-                            Box::pin(self.run(task)).await;
-                        }
-                        ("template", template) => {
-                            let _ = self.execute_template(
-                                task_name,
-                                template,
-                                &Table::new(),
-                                &mut context,
-                                &mut output,
-                                &mut error,
-                            );
-                        }
-                        ("plugin", plugin) => {
-                            let _ = self
-                                .execute_plugin(plugin, &Table::new(), &mut context)
-                                .await;
-                        }
-                        _ => panic!("Invalid dependency: {}", task),
-                    },
-                    Dependency::Status(dep) => match parse_dependency(dep.keys().next().unwrap()) {
-                        ("task", task) => {
-                            Box::pin(self.run(task)).await;
-                        }
-                        ("template", template) => {
-                            let _ = self.execute_template(
-                                task_name,
-                                template,
-                                &Table::new(),
-                                &mut context,
-                                &mut output,
-                                &mut error,
-                            );
-                        }
-                        ("plugin", plugin) => {
-                            let _ = self
-                                .execute_plugin(
-                                    plugin,
-                                    &context.resolve_table(
-                                        dep.values()
-                                            .next()
-                                            .unwrap_or(&Value::Table(Table::new()))
-                                            .as_table()
-                                            .unwrap(),
-                                    ),
-                                    &mut context,
-                                )
-                                .await;
-                        }
-                        _ => panic!("Invalid dependency: {}", dep.keys().next().unwrap()),
-                    },
-                }
+    fn task_context(&self, task_name: &str) -> Context {
+        let mut context = self.workflow_context();
+        context.set("XTOMATE_TASK".to_string(), task_name.to_string());
+        if let Some(task) = self.workflow.get_task(task_name) {
+            for (variable, value) in task.matrix_values.iter() {
+                context.set(format!("matrix.{}", variable), value.clone());
             }
         }
+        self.add_outputs(&mut context);
+        context
     }
 
-    async fn execute_template(
+    fn add_outputs(&self, context: &mut Context) {
+        for (key, value) in self.outputs.lock().expect("Failed to lock mutex").iter() {
+            context.set(key.clone(), value.clone());
+        }
+    }
+
+    /// Collects the declared outputs of a task that has succeeded.
+    fn capture_outputs(
         &self,
         task_name: &str,
-        template_name: &str,
-        environment: &Table,
-        context: &mut Context,
-        cmd_output: &mut String,
-        error: &mut String,
-    ) -> bool {
-        let template = self
-            .workflow
-            .get_template(template_name)
-            .expect("Template not found");
-
-        let mut env: Vec<(String, String)> = vec![];
-        for (key, value) in environment.iter() {
-            let resolved_value = context.resolve(value.as_str().unwrap());
-            env.push((key.clone(), resolved_value));
+        task: &Task,
+        output: &CommandOutput,
+        cwd: Option<&Path>,
+        context: &Context,
+    ) -> Result<(), Cause> {
+        let mut values = vec![];
+        for (name, source) in task.outputs.iter().flatten() {
+            let value = match source {
+                OutputSource::Stream(OutputStream::Stdout) => output.stdout.trim_end().to_string(),
+                OutputSource::Stream(OutputStream::Stderr) => output.stderr.trim_end().to_string(),
+                OutputSource::Regex { regex } => {
+                    let captures = regex::Regex::new(regex)
+                        .map_err(|e| format!("output `{}`: {}", name, e))?
+                        .captures(&output.stdout)
+                        .ok_or_else(|| {
+                            format!("output `{}`: `{}` doesn't match stdout", name, regex)
+                        })?;
+                    captures
+                        .get(1)
+                        .or_else(|| captures.get(0))
+                        .map(|m| m.as_str().to_string())
+                        .unwrap_or_default()
+                }
+                OutputSource::File { file } => {
+                    // Relative to the directory the command ran in, like `sources`
+                    let path = cwd.unwrap_or(Path::new("")).join(context.resolve(file));
+                    std::fs::read_to_string(&path)
+                        .map_err(|e| {
+                            format!(
+                                "output `{}`: could not read `{}`: {}",
+                                name,
+                                path.display(),
+                                e
+                            )
+                        })?
+                        .trim_end()
+                        .to_string()
+                }
+            };
+            values.push((output_key(task_name, name), value));
         }
-        if let Some(template_env) = template.get_env() {
-            for (key, value) in template_env.iter() {
-                let resolved_value = context.resolve(value.as_str().unwrap());
-                env.push((key.clone(), resolved_value));
+        self.outputs
+            .lock()
+            .expect("Failed to lock mutex")
+            .extend(values);
+        Ok(())
+    }
+
+    async fn execute_task(&self, task_name: &str) {
+        let task = self.workflow.get_task(task_name).unwrap();
+        let mut context = self.task_context(task_name);
+
+        let fingerprint = match self.fingerprint(task_name, &context) {
+            Ok(fingerprint) => fingerprint,
+            Err(e) => {
+                report(&e);
+                None
+            }
+        };
+        if let Some((hash, dir)) = &fingerprint {
+            if let Some(stored) = self.up_to_date(task_name, hash, dir, &context) {
+                eprintln!("Task `{}` is up to date", task_name);
+                self.outputs
+                    .lock()
+                    .expect("Failed to lock mutex")
+                    .extend(stored.outputs);
+                self.set_status(task_name, TaskStatus::UpToDate);
+                return;
             }
         }
+        if let Some(store) = &self.fingerprints {
+            store.remove(task_name);
+        }
 
-        if let Some(dependencies) = template.get_dependencies() {
-            for dep in dependencies.iter() {
-                match dep {
-                    Dependency::Simple(dependency) => {
-                        if self.needs_run(dependency) {
-                            Box::pin(self.run(dependency)).await;
-                        }
-                        if !self.check_dependency_status(dependency, "success") {
-                            panic!("Dependency failed: {}, terminating workflow!", dependency);
-                        }
-                    }
-                    Dependency::Status(dep) => {
-                        let dependency = dep.keys().next().unwrap();
-                        let required_status = dep.get(dependency).unwrap().as_str().unwrap();
-                        if self.needs_run(dependency) {
-                            Box::pin(self.run(dependency)).await;
-                        }
-                        if !self.check_dependency_status(dependency, required_status) {
-                            panic!(
-                                "Dependency did not satisfy state {}: {}, terminating workflow!",
-                                required_status, dependency
-                            );
-                        }
+        self.run_hooks(task_name, task.get_on_start(), &mut context)
+            .await;
+
+        let mut output = CommandOutput::default();
+
+        let slots = self.acquire_slots(task).await;
+        let started = Instant::now();
+        let mut cwd = None;
+        let mut status = match self.resolve_task(task_name, &mut context) {
+            Ok(Action::Command(command)) => {
+                cwd = command.cwd.clone();
+                self.execute_command(task_name, &command, &mut output).await
+            }
+            Ok(Action::Plugin { name, config }) => {
+                match self.execute_plugin(&name, &config).await {
+                    Ok(true) => TaskStatus::Success,
+                    Ok(false) => TaskStatus::Failed,
+                    Err(cause) => {
+                        report(&Error::Task {
+                            task: task_name.to_string(),
+                            cause,
+                        });
+                        TaskStatus::Failed
                     }
                 }
             }
+            Err(e) => {
+                report(&e);
+                TaskStatus::Failed
+            }
+        };
+        let duration = started.elapsed();
+        drop(slots);
+
+        if status == TaskStatus::Success {
+            if let Err(cause) =
+                self.capture_outputs(task_name, task, &output, cwd.as_deref(), &context)
+            {
+                report(&Error::Task {
+                    task: task_name.to_string(),
+                    cause,
+                });
+                status = TaskStatus::Failed;
+            }
+        }
+        if let (TaskStatus::Success, Some((hash, _))) = (status, fingerprint) {
+            self.save_fingerprint(task_name, task, hash);
         }
 
-        let output = tokio::process::Command::new("sh")
-            .arg("-c")
-            .arg(context.resolve(template.command.as_ref().unwrap()))
-            .envs(env.clone())
-            .output()
-            .await;
-
-        let mut success = output
-            .map(|o| {
-                if !o.stdout.is_empty() {
-                    println!(
-                        "Task `{}` stdout:\n{}",
-                        task_name,
-                        String::from_utf8_lossy(&o.stdout)
-                    );
-                    cmd_output.push_str(&String::from_utf8_lossy(&o.stdout));
-                }
-                if !o.stderr.is_empty() {
-                    eprintln!(
-                        "Task `{}` stderr:\n{}",
-                        task_name,
-                        String::from_utf8_lossy(&o.stderr)
-                    );
-                    error.push_str(&String::from_utf8_lossy(&o.stderr));
-                }
-                o.status.success()
-            })
-            .unwrap_or(false);
-
-        if let Some(retry) = template.retry {
-            let mut retries = 0;
-            while !success && retries < retry {
-                retries += 1;
-                if let Some(delay) = template.retry_delay {
-                    tokio::time::sleep(std::time::Duration::from_secs(delay as u64)).await;
-                }
+        context.set("XTOMATE_STDERR".to_string(), output.stderr);
+        context.set("XTOMATE_STDOUT".to_string(), output.stdout);
+        context.set(
+            "XTOMATE_ATTEMPT".to_string(),
+            output.attempts.len().max(1).to_string(),
+        );
+        if let Some(task) = self.tasks.get(task_name) {
+            *task.attempts.lock().expect("Failed to lock mutex") = output.attempts;
+            *task.duration.lock().expect("Failed to lock mutex") = Some(duration);
+        }
 
-                let output = tokio::process::Command::new("sh")
-                    .arg("-c")
-                    .arg(context.resolve(template.command.as_ref().unwrap()))
-                    .envs(env.clone())
-                    .output()
-                    .await;
+        // Set before running the hooks, which may run tasks that depend on this one
+        self.set_status(task_name, status);
 
-                success = output.map(|o| o.status.success()).unwrap_or(false);
-            }
+        if status != TaskStatus::Success && status != TaskStatus::Interrupted {
+            self.run_hooks(task_name, task.get_on_error(), &mut context)
+                .await;
         }
 
-        success
+        if status != TaskStatus::Interrupted {
+            self.run_hooks(task_name, task.get_on_finish(), &mut context)
+                .await;
+        }
     }
 
-    async fn execute_command(
+    /// The fingerprint of a task with `sources` and the directory its `sources` and `artifacts`
+    /// are relative to, or `None` if it has no sources or fingerprints aren't kept.
+    fn fingerprint(
         &self,
         task_name: &str,
-        command: &str,
-        environment: &Table,
-        retry: usize,
-        retry_delay: usize,
-        context: &mut Context,
-        cmd_output: &mut String,
-        error: &mut String,
-    ) -> bool {
-        let mut env: Vec<(String, String)> = vec![];
+        context: &Context,
+    ) -> Result<Option<(String, PathBuf)>, Error> {
+        let task = self.workflow.get_task(task_name).unwrap();
+        let (Some(sources), Some(_)) = (&task.sources, &self.fingerprints) else {
+            return Ok(None);
+        };
+        let (parts, dir) = match self.resolve_task(task_name, &mut context.clone())? {
+            Action::Command(command) => {
+                let mut parts = command.program.argv();
+                parts.extend(
+                    command
+                        .env
+                        .iter()
+                        .map(|(key, value)| format!("{}={}", key, value)),
+                );
+                let dir = command.cwd.unwrap_or_default();
+                parts.push(dir.to_string_lossy().to_string());
+                (parts, dir)
+            }
+            Action::Plugin { name, config } => (vec![name, config], PathBuf::new()),
+        };
+        let sources: Vec<String> = sources.iter().map(|s| context.resolve(s)).collect();
+        let hash = fingerprint::hash(&dir, &sources, &parts).map_err(|e| Error::Task {
+            task: task_name.to_string(),
+            cause: format!("sources: {}", e).into(),
+        })?;
+        Ok(Some((hash, dir)))
+    }
 
-        for (key, value) in environment.iter() {
-            let resolved_value = context.resolve(value.as_str().unwrap());
-            env.push((key.clone(), resolved_value));
+    /// The fingerprint stored when the task last succeeded, if it is still `hash` and all of
+    /// the task's artifacts exist.
+    fn up_to_date(
+        &self,
+        task_name: &str,
+        hash: &str,
+        dir: &Path,
+        context: &Context,
+    ) -> Option<Fingerprint> {
+        if self.force {
+            return None;
         }
+        let stored = self.fingerprints.as_ref()?.load(task_name)?;
+        let task = self.workflow.get_task(task_name)?;
+        let artifacts: Vec<String> = task
+            .artifacts
+            .iter()
+            .flatten()
+            .map(|artifact| context.resolve(artifact))
+            .collect();
+        (stored.hash == hash && fingerprint::artifacts_exist(dir, &artifacts) == Ok(true))
+            .then_some(stored)
+    }
 
-        let output = tokio::process::Command::new("sh")
-            .arg("-c")
-            .arg(command)
-            .envs(env.clone())
-            .output()
-            .await;
-
-        let mut success = output
-            .map(|o| {
-                if !o.stdout.is_empty() {
-                    println!(
-                        "Task `{}` stdout:\n{}",
-                        task_name,
-                        String::from_utf8_lossy(&o.stdout)
-                    );
-                    cmd_output.push_str(&String::from_utf8_lossy(&o.stdout));
-                }
-                if !o.stderr.is_empty() {
-                    eprintln!(
-                        "Task `{}` stderr:\n{}",
-                        task_name,
-                        String::from_utf8_lossy(&o.stderr)
-                    );
-                    error.push_str(&String::from_utf8_lossy(&o.stderr));
-                }
-                o.status.success()
-            })
-            .unwrap_or(false);
-
-        if retry > 0 {
-            let mut retries = 0;
-            while !success && retries < retry {
-                retries += 1;
-                if retry_delay > 0 {
-                    tokio::time::sleep(std::time::Duration::from_secs(retry_delay as u64)).await;
-                }
-
-                let output = tokio::process::Command::new("sh")
-                    .arg("-c")
-                    .arg(command)
-                    .envs(env.clone())
-                    .output()
-                    .await;
+    fn save_fingerprint(&self, task_name: &str, task: &Task, hash: String) {
+        let Some(store) = &self.fingerprints else {
+            return;
+        };
+        let all_outputs = self.outputs.lock().expect("Failed to lock mutex");
+        let outputs = task
+            .outputs
+            .iter()
+            .flatten()
+            .map(|(name, _)| output_key(task_name, name))
+            .filter_map(|key| Some((key.clone(), all_outputs.get(&key)?.clone())))
+            .collect();
+        if let Err(e) = store.save(task_name, &Fingerprint { hash, outputs }) {
+            warn(&format!(
+                "could not save the fingerprint of `{}`: {}",
+                task_name, e
+            ));
+        }
+    }
 
-                success = output.map(|o| o.status.success()).unwrap_or(false);
+    /// Waits for a slot in every resource pool the task uses and then for one of the
+    /// `max_parallel` slots. Pools are always claimed in the same order, so two tasks can't
+    /// each hold a pool the other is waiting for.
+    async fn acquire_slots(&self, task: &Task) -> Vec<SemaphorePermit<'_>> {
+        let mut pools: Vec<&String> = task.resources.iter().flatten().collect();
+        pools.sort();
+        pools.dedup();
+
+        let semaphores = pools
+            .into_iter()
+            .filter_map(|pool| self.pools.get(pool))
+            .chain(self.parallel.as_ref());
+
+        let mut slots = vec![];
+        for semaphore in semaphores {
+            if let Ok(slot) = semaphore.acquire().await {
+                slots.push(slot);
             }
         }
-
-        success
+        slots
     }
 
-    async fn execute_plugin(
-        &self,
-        plugin_name: &str,
-        config: &Table,
-        context: &mut Context,
-    ) -> bool {
-        let plugin = self
-            .plugins
-            .iter()
-            .find(|p| &p.name == plugin_name)
-            .expect("Plugin not found");
+    /// Resolves what running a task would do: its final command and environment after
+    /// template expansion and placeholder resolution, or the plugin it calls and the
+    /// configuration passed to it.
+    fn resolve_task(&self, task_name: &str, context: &mut Context) -> Result<Action, Error> {
+        let task_error = |cause: Cause| Error::Task {
+            task: task_name.to_string(),
+            cause,
+        };
+        let task = self
+            .workflow
+            .get_task(task_name)
+            .ok_or_else(|| task_error("no such task".into()))?;
+        let environment = task.get_env().cloned().unwrap_or_default();
+
+        let timeout = task
+            .timeout
+            .as_deref()
+            .map(parse_duration)
+            .transpose()
+            .map_err(|e| task_error(e.into()))?;
+
+        let cwd = task
+            .cwd
+            .as_deref()
+            .map(|cwd| self.resolve_cwd(cwd, context));
+
+        if let Some(template) = &task.template {
+            if let Some(config) = task.get_config() {
+                for (key, value) in config.iter() {
+                    context.set(key.clone(), value_to_string(value));
+                }
+            }
+            let mut command = self
+                .resolve_template(template, &environment, &task.retry_options, context)
+                .map_err(task_error)?;
+            command.timeout = timeout.or(command.timeout);
+            command.cwd = cwd.or(command.cwd);
+            if let (Program::Shell { shell, .. }, Some(task_shell)) =
+                (&mut command.program, &task.shell)
+            {
+                *shell = task_shell.clone();
+            }
+            Ok(Action::Command(Box::new(command)))
+        } else if let Some(program) = resolve_program(
+            task.command.as_ref(),
+            task.exec.as_ref(),
+            task.shell.as_ref(),
+            context,
+        ) {
+            Ok(Action::Command(Box::new(ResolvedCommand {
+                program,
+                cwd,
+                env: resolve_env(&environment, context),
+                retry: RetryPolicy::new(&task.retry_options).map_err(|e| task_error(e.into()))?,
+                timeout,
+            })))
+        } else if let Some(plugin) = &task.plugin {
+            Ok(Action::Plugin {
+                name: plugin.clone(),
+                config: resolve_plugin_config(task.get_config().unwrap_or(&Table::new()), context),
+            })
+        } else {
+            Err(task_error(
+                "has no command, exec, plugin or template".into(),
+            ))
+        }
+    }
 
-        let config_resolved = context.resolve(&serde_json::to_string(&config).unwrap());
+    fn resolve_cwd(&self, cwd: &str, context: &Context) -> PathBuf {
+        let cwd = PathBuf::from(context.resolve(cwd));
+        match &self.base_dir {
+            Some(base_dir) => base_dir.join(cwd),
+            None => cwd,
+        }
+    }
 
-        unsafe {
-            let execute: Symbol<unsafe extern "C" fn(*const c_char) -> i32> =
-                plugin.plugin.get(b"execute").unwrap();
+    fn resolve_template(
+        &self,
+        template_name: &str,
+        environment: &Table,
+        retry_options: &RetryOptions,
+        context: &Context,
+    ) -> Result<ResolvedCommand, Cause> {
+        let template = self
+            .workflow
+            .get_template(template_name)
+            .ok_or_else(|| format!("template `{}` is not defined", template_name))?;
+        let program = resolve_program(
+            template.command.as_ref(),
+            template.exec.as_ref(),
+            template.shell.as_ref(),
+            context,
+        )
+        .ok_or_else(|| format!("template `{}` has no command or exec", template_name))?;
+
+        let mut env = resolve_env(environment, context);
+        env.extend(resolve_env(
+            template.get_env().unwrap_or(&Table::new()),
+            context,
+        ));
+
+        Ok(ResolvedCommand {
+            program,
+            cwd: template
+                .cwd
+                .as_deref()
+                .map(|cwd| self.resolve_cwd(cwd, context)),
+            env,
+            retry: RetryPolicy::new(&retry_options.or(&template.retry_options))?,
+            timeout: template
+                .timeout
+                .as_deref()
+                .map(parse_duration)
+                .transpose()?,
+        })
+    }
 
-            let config_cstr = CString::new(config_resolved).unwrap();
-            execute(config_cstr.as_ptr());
+    /// Runs the tasks, templates and plugins listed in one of the `on_start`, `on_finish` or
+    /// `on_error` hooks. A hook that can't be run is reported and skipped.
+    async fn run_hooks(
+        &self,
+        task_name: &str,
+        hooks: Option<&Vec<Dependency>>,
+        context: &mut Context,
+    ) {
+        for hook in hooks.into_iter().flatten() {
+            let (target, config) = hook_target(hook);
+            let result = match parse_dependency(target) {
+                ("task", task) => {
+                    Box::pin(self.run_once(task)).await;
+                    Ok(())
+                }
+                ("template", template) => self
+                    .execute_template(
+                        task_name,
+                        template,
+                        &Table::new(),
+                        context,
+                        &mut CommandOutput::default(),
+                    )
+                    .await
+                    .map(|_| ()),
+                ("plugin", plugin) => self
+                    .execute_plugin(plugin, &resolve_plugin_config(&config, context))
+                    .await
+                    .map(|_| ()),
+                (kind, _) => Err(format!("unknown hook kind `{}`", kind).into()),
+            };
+            if let Err(cause) = result {
+                report(&Error::Hook {
+                    task: task_name.to_string(),
+                    hook: target.to_string(),
+                    cause,
+                });
+            }
         }
-
-        true
     }
 
-    pub async fn run_all(self: Arc<Self>) {
-        let mut context = Context::new();
+    async fn execute_template(
+        &self,
+        task_name: &str,
+        template_name: &str,
+        environment: &Table,
+        context: &Context,
+        output: &mut CommandOutput,
+    ) -> Result<bool, Cause> {
+        if let Some(template) = self.workflow.get_template(template_name) {
+            if self
+                .run_dependencies(template.get_dependencies())
+                .await
+                .is_err()
+            {
+                return Ok(false);
+            }
+        }
 
-        context.set("XTOMATE_WORKFLOW".to_string(), self.workflow.name.clone());
+        let command = self.resolve_template(
+            template_name,
+            environment,
+            &RetryOptions::default(),
+            context,
+        )?;
+        Ok(self.execute_command(task_name, &command, output).await == TaskStatus::Success)
+    }
 
-        if let Some(on_start) = self.workflow.get_on_start() {
-            for finish in on_start.iter() {
-                match finish {
-                    Dependency::Simple(task) => match parse_dependency(task) {
-                        ("task", task) => {
-                            Box::pin(self.run(task)).await;
-                        }
-                        ("template", template) => {
-                            let _ = self.execute_template(
-                                "on_start",
-                                template,
-                                &Table::new(),
-                                &mut context,
-                                &mut String::new(),
-                                &mut String::new(),
-                            );
-                        }
-                        ("plugin", plugin) => {
-                            let _ = self
-                                .execute_plugin(plugin, &Table::new(), &mut context)
-                                .await;
-                        }
-                        _ => panic!("Invalid dependency: {}", task),
-                    },
-                    Dependency::Status(dep) => match parse_dependency(dep.keys().next().unwrap()) {
-                        ("task", task) => {
-                            Box::pin(self.run(task)).await;
-                        }
-                        ("template", template) => {
-                            let _ = self.execute_template(
-                                "on_start",
-                                template,
-                                &Table::new(),
-                                &mut context,
-                                &mut String::new(),
-                                &mut String::new(),
-                            );
+    /// Runs a command, retrying it as its retry policy allows if it fails or times out. Every
+    /// attempt is killed if it is still running after the command's timeout or at the
+    /// workflow's deadline, and is recorded in `output`, which otherwise keeps what the last
+    /// attempt printed.
+    async fn execute_command(
+        &self,
+        task_name: &str,
+        command: &ResolvedCommand,
+        output: &mut CommandOutput,
+    ) -> TaskStatus {
+        let attempts = command.retry.retries + 1;
+        let task_log = self.log.as_ref().and_then(|log| {
+            log.task_log(task_name)
+                .map_err(|e| {
+                    warn(&format!(
+                        "could not open the logs of task `{}`: {}",
+                        task_name, e
+                    ))
+                })
+                .ok()
+        });
+        loop {
+            if self.interrupted() {
+                return TaskStatus::Interrupted;
+            }
+            let attempt = output.attempts.len() + 1;
+            let deadline = [
+                command.timeout.map(|timeout| Instant::now() + timeout),
+                self.deadline.get().copied(),
+            ]
+            .into_iter()
+            .flatten()
+            .min();
+
+            let mut env = command.env.clone();
+            env.push(("XTOMATE_ATTEMPT".to_string(), attempt.to_string()));
+            let grouped = self.output_mode() == OutputMode::Grouped;
+            if let Some(task_log) = &task_log {
+                if attempt > 1 {
+                    let header = format!("--- attempt {} ---\n", attempt);
+                    let _ = task_log.write(Stream::Stdout, header.as_bytes());
+                    let _ = task_log.write(Stream::Stderr, header.as_bytes());
+                }
+            }
+            let started = Instant::now();
+            let result = self
+                .processes
+                .run(
+                    &command.program.argv(),
+                    command.cwd.as_deref(),
+                    &env,
+                    deadline,
+                    |stream, line| {
+                        if !grouped {
+                            print_line(task_name, stream, line);
                         }
-                        ("plugin", plugin) => {
-                            let _ = self
-                                .execute_plugin(
-                                    plugin,
-                                    &context.resolve_table(
-                                        dep.values()
-                                            .next()
-                                            .unwrap_or(&Value::Table(Table::new()))
-                                            .as_table()
-                                            .unwrap(),
-                                    ),
-                                    &mut context,
-                                )
-                                .await;
+                        if let Some(task_log) = &task_log {
+                            let _ = task_log.write(stream, line);
                         }
-                        _ => panic!("Invalid dependency: {}", dep.keys().next().unwrap()),
                     },
+                )
+                .await;
+            output.attempts.push(Attempt {
+                status: result.status,
+                exit_code: result.exit_code,
+                duration: started.elapsed(),
+            });
+            output.stdout = String::from_utf8_lossy(&result.stdout).into_owned();
+            output.stderr = String::from_utf8_lossy(&result.stderr).into_owned();
+            if grouped && !output.stdout.is_empty() {
+                println!("Task `{}` stdout:\n{}", task_name, output.stdout);
+            }
+            if grouped && !output.stderr.is_empty() {
+                eprintln!("Task `{}` stderr:\n{}", task_name, output.stderr);
+            }
+            if result.status == TaskStatus::TimedOut {
+                eprintln!("Task `{}` timed out and was killed", task_name);
+            }
+
+            if result.status == TaskStatus::Interrupted {
+                eprintln!("Task `{}` was interrupted", task_name);
+            }
+
+            let mut summary = format!("attempt {}/{} {}", attempt, attempts, result.status);
+            if let Some(exit_code) = result.exit_code {
+                let _ = write!(summary, " (exit code {})", exit_code);
+            }
+            let _ = write!(summary, " after {:?}", round_to_millis(started.elapsed()));
+            let retry = !matches!(result.status, TaskStatus::Success | TaskStatus::Interrupted)
+                && attempt < attempts
+                && command.retry.should_retry(result.exit_code, &output.stderr);
+            let delay = command.retry.delay(attempt);
+            if retry {
+                let _ = write!(summary, ", retrying in {:?}", round_to_millis(delay));
+                eprintln!("Task `{}` {}", task_name, summary);
+            }
+            if let Some(log) = self.log.as_ref().filter(|_| attempts > 1) {
+                if let Err(e) = log.log_retry(task_name, &summary) {
+                    warn(&format!(
+                        "could not log the retries of task `{}`: {}",
+                        task_name, e
+                    ));
                 }
             }
+
+            if !retry {
+                return result.status;
+            }
+            if !delay.is_zero() {
+                tokio::time::sleep(delay).await;
+            }
         }
+    }
 
-        for stage in self.order.iter() {
-            let mut handles = vec![];
-            for task in stage {
-                let self_clone = Arc::clone(&self);
-                let task_name = task.clone();
-                let handle = tokio::task::spawn(async move {
-                    self_clone.run(&task_name).await;
-                });
-                handles.push(handle);
+    /// Calls a plugin's `execute` function, returning whether it succeeded.
+    async fn execute_plugin(&self, plugin_name: &str, config: &str) -> Result<bool, Cause> {
+        let plugin = self
+            .plugins
+            .iter()
+            .find(|p| p.name == plugin_name)
+            .ok_or_else(|| format!("plugin `{}` is not loaded", plugin_name))?;
+
+        unsafe {
+            let execute: Symbol<unsafe extern "C" fn(*const c_char) -> i32> =
+                plugin.plugin.get(b"execute").map_err(|e| {
+                    format!("plugin `{}` has no `execute` function: {}", plugin_name, e)
+                })?;
+
+            let config_cstr = CString::new(config)?;
+            Ok(execute(config_cstr.as_ptr()) == 0)
+        }
+    }
+
+    /// Runs every planned task, stage by stage, and reports how each of them finished.
+    pub async fn run_all(self: Arc<Self>) -> RunResult {
+        let mut context = self.workflow_context();
+
+        if let Some(Ok(timeout)) = self.workflow.timeout.as_deref().map(parse_duration) {
+            let _ = self.deadline.set(Instant::now() + timeout);
+        }
+
+        self.run_hooks("on_start", self.workflow.get_on_start(), &mut context)
+            .await;
+
+        // Every task starts as soon as all of its dependencies have finished, instead of
+        // waiting for the rest of its stage.
+        let mut waiting: Vec<&String> = self.order.iter().flatten().collect();
+        let mut finished: HashSet<&str> = HashSet::new();
+        let mut running = JoinSet::new();
+        let mut running_names = HashMap::new();
+        loop {
+            let mut still_waiting = vec![];
+            let waiting_count = waiting.len();
+            for task in waiting {
+                let ready = self.dependencies[task]
+                    .iter()
+                    .all(|dependency| finished.contains(dependency.as_str()));
+                if !ready || self.stopping() {
+                    still_waiting.push(task);
+                } else if self.status(task).is_some() {
+                    // Already run by a hook
+                    finished.insert(task);
+                } else if !self.claim(task) {
+                    // Being run by a hook, which belongs to a task that is still running
+                    still_waiting.push(task);
+                } else {
+                    let self_clone = Arc::clone(&self);
+                    let task_name = task.clone();
+                    let handle = running.spawn(async move {
+                        self_clone.run(&task_name).await;
+                    });
+                    running_names.insert(handle.id(), task);
+                }
+            }
+            let progress = still_waiting.len() < waiting_count;
+            waiting = still_waiting;
+
+            let (id, result) = match running.join_next_with_id().await {
+                Some(Ok((id, ()))) => (id, Ok(())),
+                Some(Err(e)) => (e.id(), Err(e)),
+                // Nothing is running, so unless a task was just found to be finished, the
+                // waiting tasks can never become ready
+                None if progress && !waiting.is_empty() && !self.stopping() => continue,
+                None => break,
+            };
+            let task = running_names[&id];
+            if let Err(e) = result {
+                eprintln!("Task `{}` crashed: {}", task, e);
+                self.set_status(task, TaskStatus::Failed);
             }
+            finished.insert(task);
+        }
+
+        self.finishing.store(true, Ordering::SeqCst);
+        self.add_outputs(&mut context);
+        self.run_hooks("on_finish", self.workflow.get_on_finish(), &mut context)
+            .await;
+
+        self.teardown();
 
-            for handle in handles {
-                handle.await.unwrap();
+        let result = RunResult {
+            tasks: self
+                .order
+                .iter()
+                .flatten()
+                .map(|task| TaskResult {
+                    name: task.clone(),
+                    status: self.status(task).unwrap_or(TaskStatus::NotRun),
+                    attempts: self
+                        .tasks
+                        .get(task)
+                        .map(|t| t.attempts.lock().expect("Failed to lock mutex").clone())
+                        .unwrap_or_default(),
+                    duration: self
+                        .tasks
+                        .get(task)
+                        .and_then(|t| *t.duration.lock().expect("Failed to lock mutex")),
+                })
+                .collect(),
+            interrupted: self.processes.interrupted().is_some(),
+        };
+        if let Some(log) = &self.log {
+            if let Err(e) = log.finish(&result) {
+                warn(&format!("could not save the run log: {}", e));
             }
         }
+        result
+    }
 
-        if let Some(on_finish) = self.workflow.get_on_finish() {
-            for finish in on_finish.iter() {
-                match finish {
-                    Dependency::Simple(task) => match parse_dependency(task) {
-                        ("task", task) => {
-                            Box::pin(self.run(task)).await;
-                        }
-                        ("template", template) => {
-                            let _ = self.execute_template(
-                                "on_finish",
-                                template,
-                                &Table::new(),
-                                &mut context,
-                                &mut String::new(),
-                                &mut String::new(),
-                            );
+    /// Describes what `run_all` would do without running anything: the stages, and for every
+    /// task its resolved command, environment or plugin configuration and the hooks it has.
+    pub fn describe_plan(&self) -> String {
+        let mut plan = String::new();
+        let context = self.workflow_context();
+
+        let _ = writeln!(plan, "Workflow `{}`", self.workflow.name);
+        if let Some(jobs) = self.jobs.or(self.workflow.max_parallel) {
+            let _ = writeln!(plan, "  at most {} task(s) at a time", jobs);
+        }
+        for (name, value) in self.params.iter() {
+            let _ = writeln!(plan, "  param {} = {}", name, value);
+        }
+        self.describe_hooks(
+            &mut plan,
+            "  ",
+            "on_start",
+            self.workflow.get_on_start(),
+            &context,
+        );
+
+        for (index, stage) in self.order.iter().enumerate() {
+            let _ = writeln!(plan, "Stage {}:", index + 1);
+            for task_name in stage {
+                let task = self.workflow.get_task(task_name).unwrap();
+                let mut context = self.task_context(task_name);
+                let _ = writeln!(plan, "  {}", task_name);
+                if let Some(resources) = &task.resources {
+                    let _ = writeln!(plan, "    resources: {}", resources.join(", "));
+                }
+                for condition in self.workflow.conditions(task_name) {
+                    let _ = writeln!(plan, "    when: {}", condition);
+                }
+                if let Some(outputs) = &task.outputs {
+                    let mut names: Vec<&str> = outputs.keys().map(|k| k.as_str()).collect();
+                    names.sort();
+                    let _ = writeln!(plan, "    outputs: {}", names.join(", "));
+                }
+                if let Some(sources) = &task.sources {
+                    let _ = writeln!(plan, "    sources: {}", sources.join(", "));
+                }
+                if let Some(artifacts) = &task.artifacts {
+                    let _ = writeln!(plan, "    artifacts: {}", artifacts.join(", "));
+                }
+                if let Ok(Some((hash, dir))) = self.fingerprint(task_name, &context) {
+                    if self.up_to_date(task_name, &hash, &dir, &context).is_some() {
+                        let _ = writeln!(plan, "    up to date");
+                    }
+                }
+
+                match self.resolve_task(task_name, &mut context) {
+                    Ok(Action::Command(command)) => {
+                        match &command.program {
+                            Program::Shell { shell, command } => {
+                                let _ = writeln!(
+                                    plan,
+                                    "    command: {}",
+                                    command.trim_end().replace('\n', "\n             ")
+                                );
+                                if *shell != Shell::default() {
+                                    let _ = writeln!(
+                                        plan,
+                                        "    shell: {}",
+                                        quote_args(&shell.prefix())
+                                    );
+                                }
+                            }
+                            Program::Exec(_) => {
+                                let _ = writeln!(plan, "    exec: {}", command.program);
+                            }
                         }
-                        ("plugin", plugin) => {
-                            let _ = self
-                                .execute_plugin(plugin, &Table::new(), &mut context)
-                                .await;
+                        if let Some(cwd) = &command.cwd {
+                            let _ = writeln!(plan, "    cwd: {}", cwd.display());
                         }
-                        _ => panic!("Invalid dependency: {}", task),
-                    },
-                    Dependency::Status(dep) => match parse_dependency(dep.keys().next().unwrap()) {
-                        ("task", task) => {
-                            Box::pin(self.run(task)).await;
+                        for (key, value) in command.env.iter() {
+                            let _ = writeln!(plan, "    env: {}={}", key, value);
                         }
-                        ("template", template) => {
-                            let _ = self.execute_template(
-                                "on_finish",
-                                template,
-                                &Table::new(),
-                                &mut context,
-                                &mut String::new(),
-                                &mut String::new(),
-                            );
+                        if let Some(timeout) = command.timeout {
+                            let _ = writeln!(plan, "    timeout: {:?}", timeout);
                         }
-                        ("plugin", plugin) => {
-                            let _ = self
-                                .execute_plugin(
-                                    plugin,
-                                    &context.resolve_table(
-                                        dep.values()
-                                            .next()
-                                            .unwrap_or(&Value::Table(Table::new()))
-                                            .as_table()
-                                            .unwrap(),
-                                    ),
-                                    &mut context,
-                                )
-                                .await;
+                        if command.retry.retries > 0 {
+                            let _ = writeln!(plan, "    retry: {}", command.retry);
                         }
-                        _ => panic!("Invalid dependency: {}", dep.keys().next().unwrap()),
-                    },
+                    }
+                    Ok(Action::Plugin { name, config }) => {
+                        let _ = writeln!(plan, "    plugin: {}", name);
+                        let _ = writeln!(plan, "    config: {}", config);
+                    }
+                    Err(e) => {
+                        let _ = writeln!(plan, "    error: {}", e);
+                    }
                 }
+
+                self.describe_hooks(&mut plan, "    ", "on_start", task.get_on_start(), &context);
+                self.describe_hooks(
+                    &mut plan,
+                    "    ",
+                    "on_error (if the task fails)",
+                    task.get_on_error(),
+                    &context,
+                );
+                self.describe_hooks(
+                    &mut plan,
+                    "    ",
+                    "on_finish",
+                    task.get_on_finish(),
+                    &context,
+                );
             }
         }
 
-        self.teardown();
+        if self.workflow.get_on_finish().is_some() {
+            let _ = writeln!(plan, "After all stages:");
+            self.describe_hooks(
+                &mut plan,
+                "  ",
+                "on_finish",
+                self.workflow.get_on_finish(),
+                &context,
+            );
+        }
+        plan
+    }
+
+    fn describe_hooks(
+        &self,
+        plan: &mut String,
+        indent: &str,
+        hook_kind: &str,
+        hooks: Option<&Vec<Dependency>>,
+        context: &Context,
+    ) {
+        for hook in hooks.into_iter().flatten() {
+            let (target, config) = hook_target(hook);
+            let description = match parse_dependency(target) {
+                ("task", task) => format!("task {}", task),
+                ("template", template) => {
+                    match self.resolve_template(
+                        template,
+                        &Table::new(),
+                        &RetryOptions::default(),
+                        context,
+                    ) {
+                        Ok(command) => {
+                            format!("template {}: {}", template, command.program)
+                        }
+                        Err(e) => format!("template {}: error: {}", template, e),
+                    }
+                }
+                ("plugin", plugin) => format!(
+                    "plugin {} {}",
+                    plugin,
+                    resolve_plugin_config(&config, context)
+                ),
+                _ => format!("invalid hook {}", target),
+            };
+            let _ = writeln!(plan, "{}{}: {}", indent, hook_kind, description);
+        }
     }
 
     fn check_dependency_status(&self, task: &str, status: &str) -> bool {
-        if let Some(runner_task) = self.tasks.get(task) {
-            match status {
-                "success" => runner_task.success.lock().expect("f").unwrap_or(false),
-                "failure" | "fail" => !runner_task.success.lock().expect("f").unwrap_or(true),
-                "any" => true,
-                _ => panic!("Unknown status: {}", status),
+        self.status(task)
+            .is_some_and(|task_status| task_status.satisfies(status))
+    }
+
+    /// Claims a task for running, so it isn't started a second time (say by the scheduler
+    /// and a `task:` hook at once). Returns `false` if it has already been claimed.
+    fn claim(&self, task: &str) -> bool {
+        self.tasks
+            .get(task)
+            .is_some_and(|task| !task.started.swap(true, Ordering::SeqCst))
+    }
+
+    /// Runs a task unless something else has already started it, in which case waits until
+    /// it has a status.
+    async fn run_once(&self, task: &str) {
+        if self.claim(task) {
+            return self.run(task).await;
+        }
+        let Some(runner_task) = self.tasks.get(task) else {
+            return;
+        };
+        loop {
+            // Created before checking the status, so a notification in between isn't missed
+            let finished = runner_task.finished.notified();
+            if self.status(task).is_some() {
+                return;
             }
-        } else {
-            false
+            finished.await;
         }
     }
 
-    fn needs_run(&self, task: &str) -> bool {
+    fn status(&self, task: &str) -> Option<TaskStatus> {
+        let runner_task = self.tasks.get(task)?;
+        *runner_task.status.lock().expect("Failed to lock mutex")
+    }
+
+    /// Records how a task finished. A task that already ran is never marked as skipped.
+    fn set_status(&self, task: &str, status: TaskStatus) {
         if let Some(runner_task) = self.tasks.get(task) {
-            runner_task.success.lock().expect("f").is_none()
+            let mut task_status = runner_task.status.lock().expect("Failed to lock mutex");
+            if task_status.is_none()
+                || !matches!(status, TaskStatus::Skipped | TaskStatus::NotNeeded)
+            {
+                *task_status = Some(status);
+            }
+            drop(task_status);
+            runner_task.finished.notify_waiters();
+        }
+        if matches!(status, TaskStatus::Failed | TaskStatus::TimedOut)
+            && self.failure_policy() == FailurePolicy::Stop
+            && !self.failed.swap(true, Ordering::SeqCst)
+        {
+            eprintln!(
+                "Task `{}` {}, not starting any more tasks (on_failure = \"stop\")",
+                task, status
+            );
+        }
+    }
+
+    fn output_mode(&self) -> OutputMode {
+        self.output.or(self.workflow.output).unwrap_or_default()
+    }
+
+    fn failure_policy(&self) -> FailurePolicy {
+        if self.keep_going {
+            FailurePolicy::Continue
         } else {
-            false
+            self.workflow.on_failure.unwrap_or_default()
         }
     }
 }
 
-fn parse_dependency(dep: &str) -> (&str, &str) {
+/// Colours used to tell the output of tasks running at the same time apart.
+const TASK_COLOURS: [Color; 6] = [
+    Color::Cyan,
+    Color::Magenta,
+    Color::Blue,
+    Color::Green,
+    Color::BrightCyan,
+    Color::BrightMagenta,
+];
+
+/// The colour of a task's output prefix, which stays the same from run to run.
+fn task_colour(task_name: &str) -> Color {
+    let hash = task_name.bytes().fold(0usize, |hash, b| {
+        hash.wrapping_mul(31).wrapping_add(b as usize)
+    });
+    TASK_COLOURS[hash % TASK_COLOURS.len()]
+}
+
+/// Prints one line of a command's output, prefixed with the name of its task.
+fn print_line(task_name: &str, stream: Stream, line: &[u8]) {
+    let line = String::from_utf8_lossy(line);
+    let line = line.trim_end_matches(['\n', '\r']);
+    let prefix = format!("[{}]", task_name).color(task_colour(task_name));
+    match stream {
+        Stream::Stdout => println!("{} {}", prefix, line),
+        Stream::Stderr => eprintln!("{} {}", prefix, line),
+    }
+}
+
+/// The program a task runs: `exec` if it is set, otherwise `command` run by `shell`.
+fn resolve_program(
+    command: Option<&String>,
+    exec: Option<&Vec<String>>,
+    shell: Option<&Shell>,
+    context: &Context,
+) -> Option<Program> {
+    if let Some(exec) = exec {
+        return Some(Program::Exec(
+            exec.iter().map(|arg| context.resolve(arg)).collect(),
+        ));
+    }
+    command.map(|command| Program::Shell {
+        shell: shell.cloned().unwrap_or_default(),
+        command: context.resolve(command),
+    })
+}
+
+/// Arguments joined with spaces, with the ones that need it quoted for a shell.
+fn quote_args(args: &[String]) -> String {
+    args.iter()
+        .map(|arg| {
+            if !arg.is_empty()
+                && arg
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c))
+            {
+                arg.clone()
+            } else {
+                format!("'{}'", arg.replace('\'', "'\\''"))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Splits a hook or dependency into its target and the configuration given to it (only used
+/// by plugins).
+fn hook_target(hook: &Dependency) -> (&str, Table) {
+    match hook {
+        Dependency::Simple(target) => (target, Table::new()),
+        Dependency::Status(dep) => {
+            let (target, config) = dep.iter().next().unwrap();
+            (target, config.as_table().cloned().unwrap_or_default())
+        }
+    }
+}
+
+fn resolve_env(environment: &Table, context: &Context) -> Vec<(String, String)> {
+    environment
+        .iter()
+        .map(|(key, value)| (key.clone(), context.resolve(&value_to_string(value))))
+        .collect()
+}
+
+/// Strings are used as they are, any other value as its TOML representation.
+fn value_to_string(value: &toml::Value) -> String {
+    match value.as_str() {
+        Some(value) => value.to_string(),
+        None => value.to_string(),
+    }
+}
+
+fn report(error: &Error) {
+    eprintln!("{} {}", "error:".red().bold(), error);
+}
+
+fn warn(message: &str) {
+    eprintln!("{} {}", "warning:".yellow().bold(), message);
+}
+
+fn round_to_millis(duration: Duration) -> Duration {
+    Duration::from_millis(duration.as_millis() as u64)
+}
+
+fn resolve_plugin_config(config: &Table, context: &Context) -> String {
+    serde_json::to_string(&context.resolve_table(config)).unwrap()
+}
+
+pub(super) fn parse_dependency(dep: &str) -> (&str, &str) {
     let parts: Vec<&str> = dep.splitn(2, ':').collect();
     if parts.len() == 2 {
         (parts[0], parts[1])
@@ -847,6 +1616,7 @@ fn parse_dependency(dep: &str) -> (&str, &str) {
 mod tests {
     use std::path::PathBuf;
 
+    use super::super::result::ExitStatus;
     use super::*;
 
     #[test]
@@ -860,87 +1630,767 @@ mod tests {
     }
 
     #[test]
-    fn test_needs_run() {
-        let runner = Runner {
-            workflow: WorkFlow::new("test".to_string(), "0.1.0".to_string(), None),
-            tasks: HashMap::new(),
-            order: vec![],
-            plugin_manager: plugins::manager::PluginManager::new(PathBuf::new()),
-            plugins: vec![],
-        };
+    fn test_claim() {
+        let runner = Runner::new(
+            WorkFlow::new("test".to_string(), "0.1.0".to_string(), None),
+            plugins::manager::PluginManager::new(PathBuf::new()),
+        );
 
-        assert_eq!(runner.needs_run("task1"), false);
+        assert!(!runner.claim("task1"));
     }
 
     #[test]
     fn test_check_dependency_status() {
-        let runner = Runner {
-            workflow: WorkFlow::new("test".to_string(), "0.1.0".to_string(), None),
-            tasks: HashMap::new(),
-            order: vec![],
-            plugin_manager: plugins::manager::PluginManager::new(PathBuf::new()),
-            plugins: vec![],
-        };
+        let runner = Runner::new(
+            WorkFlow::new("test".to_string(), "0.1.0".to_string(), None),
+            plugins::manager::PluginManager::new(PathBuf::new()),
+        );
 
-        assert_eq!(runner.check_dependency_status("task1", "success"), false);
+        assert!(!runner.check_dependency_status("task1", "success"));
     }
 
     #[tokio::test]
     async fn test_execute_command() {
-        let runner = Runner {
-            workflow: WorkFlow::new("test".to_string(), "0.1.0".to_string(), None),
-            tasks: HashMap::new(),
-            order: vec![],
-            plugin_manager: plugins::manager::PluginManager::new(PathBuf::new()),
-            plugins: vec![],
-        };
+        let runner = Runner::new(
+            WorkFlow::new("test".to_string(), "0.1.0".to_string(), None),
+            plugins::manager::PluginManager::new(PathBuf::new()),
+        );
 
-        let mut context = Context::new();
-        let mut cmd_output = String::new();
-        let mut error = String::new();
+        let mut output = CommandOutput::default();
 
-        let success = runner
+        let status = runner
             .execute_command(
                 "test",
-                "echo Hello",
-                &Table::new(),
-                0,
-                0,
-                &mut context,
-                &mut cmd_output,
-                &mut error,
+                &ResolvedCommand {
+                    program: Program::Shell {
+                        shell: Shell::default(),
+                        command: "echo Hello".to_string(),
+                    },
+                    cwd: None,
+                    env: vec![],
+                    retry: RetryPolicy::default(),
+                    timeout: None,
+                },
+                &mut output,
             )
             .await;
 
-        assert_eq!(success, true);
-        assert_eq!(cmd_output, "Hello\n");
-        assert_eq!(error, "");
+        assert_eq!(status, TaskStatus::Success);
+        assert_eq!(output.stdout, "Hello\n");
+        assert_eq!(output.stderr, "");
+        assert_eq!(output.attempts.len(), 1);
     }
 
     #[tokio::test]
-    async fn test_run() {
-        let runner = Runner {
-            workflow: WorkFlow::new("test".to_string(), "0.1.0".to_string(), None),
-            tasks: HashMap::new(),
-            order: vec![],
-            plugin_manager: plugins::manager::PluginManager::new(PathBuf::new()),
-            plugins: vec![],
+    async fn test_execute_command_retry() {
+        let runner = Runner::new(
+            WorkFlow::new("test".to_string(), "0.1.0".to_string(), None),
+            plugins::manager::PluginManager::new(PathBuf::new()),
+        );
+        let mut command = ResolvedCommand {
+            program: Program::Shell {
+                shell: Shell::default(),
+                command: "echo attempt $XTOMATE_ATTEMPT >&2; [ $XTOMATE_ATTEMPT -ge 3 ] || exit 75"
+                    .to_string(),
+            },
+            cwd: None,
+            env: vec![],
+            retry: RetryPolicy {
+                retries: 5,
+                delay: Duration::from_millis(10),
+                exit_codes: Some(vec![75]),
+                ..Default::default()
+            },
+            timeout: None,
         };
 
+        let mut output = CommandOutput::default();
+        let status = runner.execute_command("test", &command, &mut output).await;
+        assert_eq!(status, TaskStatus::Success);
+        assert_eq!(output.stderr, "attempt 3\n");
+        let exit_codes: Vec<_> = output.attempts.iter().map(|a| a.exit_code).collect();
+        assert_eq!(exit_codes, vec![Some(75), Some(75), Some(0)]);
+
+        // Exit codes that aren't listed aren't retried
+        command.program = Program::Exec(vec!["false".to_string()]);
+        let mut output = CommandOutput::default();
+        let status = runner.execute_command("test", &command, &mut output).await;
+        assert_eq!(status, TaskStatus::Failed);
+        assert_eq!(output.attempts.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let runner = Runner::new(
+            WorkFlow::new("test".to_string(), "0.1.0".to_string(), None),
+            plugins::manager::PluginManager::new(PathBuf::new()),
+        );
+
         runner.run("test").await;
     }
 
     #[tokio::test]
     async fn test_run_all() {
-        let runner = Arc::new(Runner {
-            workflow: WorkFlow::new("test".to_string(), "0.1.0".to_string(), None),
-            tasks: HashMap::new(),
-            order: vec![],
-            plugin_manager: plugins::manager::PluginManager::new(PathBuf::new()),
-            plugins: vec![],
-        });
+        let runner = Arc::new(Runner::new(
+            WorkFlow::new("test".to_string(), "0.1.0".to_string(), None),
+            plugins::manager::PluginManager::new(PathBuf::new()),
+        ));
+
+        let result = runner.run_all().await;
+        assert!(result.tasks.is_empty());
+        assert!(result.success());
+    }
+
+    #[tokio::test]
+    async fn test_run_all_result() {
+        let workflow: WorkFlow = toml::from_str(
+            r#"
+            name = "test"
+            version = ">=0.1.0"
+
+            [tasks.build]
+            command = "exit 1"
+
+            [tasks.test]
+            command = "echo test"
+            dependencies = ["build"]
+
+            [tasks.report]
+            command = "echo report"
+            dependencies = [{"build" = "failure"}]
+            "#,
+        )
+        .unwrap();
+
+        let mut runner = Runner::new(
+            workflow,
+            plugins::manager::PluginManager::new(PathBuf::new()),
+        );
+        runner.plan().unwrap();
+
+        let result = Arc::new(runner).run_all().await;
+        let statuses: Vec<(&str, TaskStatus)> = result
+            .tasks
+            .iter()
+            .map(|t| (t.name.as_str(), t.status))
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                ("build", TaskStatus::Failed),
+                ("report", TaskStatus::Success),
+                ("test", TaskStatus::Skipped)
+            ]
+        );
+        assert_eq!(result.exit_status(), ExitStatus::TaskFailed);
+    }
+
+    #[tokio::test]
+    async fn test_run_all_result_all_green() {
+        let workflow: WorkFlow = toml::from_str(
+            r#"
+            name = "test"
+            version = ">=0.1.0"
+
+            [tasks.build]
+            command = "echo built"
+
+            [tasks.report_failure]
+            command = "echo report"
+            dependencies = [{"build" = "failure"}]
+
+            [tasks.notify]
+            command = "echo notify"
+            dependencies = ["report_failure"]
+            "#,
+        )
+        .unwrap();
+
+        let mut runner = Runner::new(
+            workflow,
+            plugins::manager::PluginManager::new(PathBuf::new()),
+        );
+        runner.plan().unwrap();
+
+        let result = Arc::new(runner).run_all().await;
+        let statuses: Vec<(&str, TaskStatus)> = result
+            .tasks
+            .iter()
+            .map(|t| (t.name.as_str(), t.status))
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                ("build", TaskStatus::Success),
+                ("report_failure", TaskStatus::NotNeeded),
+                ("notify", TaskStatus::NotNeeded)
+            ]
+        );
+        assert_eq!(result.exit_status(), ExitStatus::Success);
+    }
+
+    #[tokio::test]
+    async fn test_task_outputs() {
+        let dir = std::env::temp_dir().join("xtomate-test-task-outputs");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let workflow: WorkFlow = toml::from_str(&format!(
+            r#"
+            name = "test"
+            version = ">=0.1.0"
+
+            [tasks.build]
+            command = "echo 'built version 1.2.3'; echo abc123 > {0}/sha"
+            outputs = {{ version = {{ regex = "version (\\S+)" }}, sha = {{ file = "{0}/sha" }} }}
+
+            [tasks.deploy]
+            command = "echo {{{{tasks.build.outputs.version}}}}-$SHA > {0}/deployed"
+            env = {{ SHA = "{{{{tasks.build.outputs.sha}}}}" }}
+
+            [tasks.lint]
+            command = "echo all good"
+            outputs = {{ version = {{ regex = "version (\\S+)" }} }}
+
+            [tasks.publish]
+            command = "echo {{{{tasks.lint.outputs.version}}}}"
+            "#,
+            dir.display()
+        ))
+        .unwrap();
+
+        let mut runner = Runner::new(
+            workflow,
+            plugins::manager::PluginManager::new(PathBuf::new()),
+        );
+        runner.plan().unwrap();
+        assert_eq!(
+            runner.order,
+            vec![vec!["build", "lint"], vec!["deploy", "publish"]]
+        );
+
+        let result = Arc::new(runner).run_all().await;
+        let statuses: Vec<(&str, TaskStatus)> = result
+            .tasks
+            .iter()
+            .map(|t| (t.name.as_str(), t.status))
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                ("build", TaskStatus::Success),
+                ("lint", TaskStatus::Failed),
+                ("deploy", TaskStatus::Success),
+                ("publish", TaskStatus::Skipped)
+            ]
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.join("deployed")).unwrap(),
+            "1.2.3-abc123\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_when_conditions() {
+        let workflow: WorkFlow = toml::from_str(
+            r#"
+            name = "test"
+            version = ">=0.1.0"
+
+            [tasks.test]
+            command = "true"
+
+            [tasks.deploy]
+            command = "echo deploying"
+            when = "env.XTOMATE_TEST_DEPLOY == 'yes' && tasks.test.status == 'success'"
+
+            [tasks.notify]
+            command = "echo deployed"
+            dependencies = ["deploy"]
+
+            [tasks.report]
+            command = "echo not deployed"
+            when = "tasks.deploy.status == 'condition_false' && XTOMATE_TASK == 'report'"
+
+            [tasks.cleanup]
+            command = "true"
+            dependencies = [{ deploy = "skipped" }]
+            "#,
+        )
+        .unwrap();
+
+        let mut runner = Runner::new(
+            workflow,
+            plugins::manager::PluginManager::new(PathBuf::new()),
+        );
+        runner.plan().unwrap();
+        assert_eq!(runner.dependencies["deploy"], vec!["test"]);
+
+        let result = Arc::new(runner).run_all().await;
+        let mut statuses: Vec<(&str, TaskStatus)> = result
+            .tasks
+            .iter()
+            .map(|t| (t.name.as_str(), t.status))
+            .collect();
+        statuses.sort_by_key(|(name, _)| *name);
+        assert_eq!(
+            statuses,
+            vec![
+                ("cleanup", TaskStatus::Success),
+                ("deploy", TaskStatus::ConditionFalse),
+                ("notify", TaskStatus::ConditionFalse),
+                ("report", TaskStatus::Success),
+                ("test", TaskStatus::Success)
+            ]
+        );
+        assert!(result.success());
+    }
+
+    #[tokio::test]
+    async fn test_cwd_shell_exec() {
+        let dir = std::env::temp_dir().join("xtomate-test-cwd");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        let dir = dir.canonicalize().unwrap();
+
+        let workflow: WorkFlow = toml::from_str(
+            r#"
+            name = "test"
+            version = ">=0.1.0"
+
+            [tasks.pwd]
+            command = "pwd > pwd.txt"
+            cwd = "sub"
+
+            [tasks.bash]
+            command = "[[ -n $BASH_VERSION ]] && echo bash > shell.txt"
+            shell = "bash"
+            cwd = "sub"
+
+            [tasks.argv]
+            command = "echo argv > argv.txt"
+            shell = ["sh", "-e", "-c"]
+            cwd = "sub"
+
+            [tasks.exec]
+            exec = ["sh", "-c", "printf '%s|' \"$@\" > exec.txt", "sh", "{{XTOMATE_TASK}} task", "*"]
+            cwd = "sub"
+
+            [tasks.sha]
+            command = "echo 1234abcd > sha"
+            cwd = "sub"
+            outputs = { sha = { file = "sha" } }
+
+            [tasks.use_sha]
+            command = "echo {{tasks.sha.outputs.sha}} > used.txt"
+            cwd = "sub"
+            "#,
+        )
+        .unwrap();
+
+        let mut runner = Runner::new(
+            workflow,
+            plugins::manager::PluginManager::new(PathBuf::new()),
+        );
+        runner.set_base_dir(dir.clone());
+        runner.plan().unwrap();
+        assert!(runner.describe_plan().contains(
+            "  exec: sh -c 'printf '\\''%s|'\\'' \"$@\" > exec.txt' sh 'exec task' '*'\n"
+        ));
+
+        let result = Arc::new(runner).run_all().await;
+        assert!(result.success());
+        let read = |file: &str| std::fs::read_to_string(dir.join("sub").join(file)).unwrap();
+        assert_eq!(read("pwd.txt"), format!("{}\n", dir.join("sub").display()));
+        assert_eq!(read("shell.txt"), "bash\n");
+        assert_eq!(read("argv.txt"), "argv\n");
+        assert_eq!(read("exec.txt"), "exec task|*|");
+        assert_eq!(read("used.txt"), "1234abcd\n");
+    }
+
+    #[tokio::test]
+    async fn test_hook_target_runs_once() {
+        let dir = std::env::temp_dir().join("xtomate-test-hook-once");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // `notify` is ready as soon as the run starts, and is also the target of `build`'s
+        // `on_start` hook
+        let workflow: WorkFlow = toml::from_str(
+            r#"
+            name = "test"
+            version = ">=0.1.0"
+
+            [tasks.build]
+            command = "true"
+            on_start = ["task:notify"]
+
+            [tasks.notify]
+            command = "echo run >> runs.txt && sleep 0.2"
+            cwd = "."
+            "#,
+        )
+        .unwrap();
+        let mut runner = Runner::new(
+            workflow,
+            plugins::manager::PluginManager::new(PathBuf::new()),
+        );
+        runner.set_base_dir(dir.clone());
+        runner.plan().unwrap();
+        let result = Arc::new(runner).run_all().await;
+        assert!(result.success());
+        assert_eq!(
+            std::fs::read_to_string(dir.join("runs.txt")).unwrap(),
+            "run\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_hook_target_depending_on_owner() {
+        // `report` is run by `build`'s `on_error` hook and waits for `build` to fail, which
+        // used to deadlock: `build` only got its status once its hooks had returned
+        let workflow: WorkFlow = toml::from_str(
+            r#"
+            name = "test"
+            version = ">=0.1.0"
+
+            [tasks.build]
+            command = "exit 1"
+            on_error = ["task:report"]
+
+            [tasks.report]
+            command = "echo report"
+            run = false
+            dependencies = [{"build" = "failure"}]
+            "#,
+        )
+        .unwrap();
+        let mut runner = Runner::new(
+            workflow,
+            plugins::manager::PluginManager::new(PathBuf::new()),
+        );
+        runner.plan().unwrap();
+        let runner = Arc::new(runner);
+        let result = tokio::time::timeout(Duration::from_secs(10), Arc::clone(&runner).run_all())
+            .await
+            .expect("the run should not hang");
+        assert_eq!(result.tasks[0].status, TaskStatus::Failed);
+        assert_eq!(runner.status("report"), Some(TaskStatus::Success));
+    }
+
+    #[tokio::test]
+    async fn test_sources() {
+        let dir = std::env::temp_dir().join("xtomate-test-sources");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::write(dir.join("src/a.txt"), "a").unwrap();
+
+        let run = |force: bool| {
+            let dir = dir.clone();
+            async move {
+                let workflow: WorkFlow = toml::from_str(
+                    r#"
+                    name = "test"
+                    version = ">=0.1.0"
+
+                    [tasks.build]
+                    command = "cat src/*.txt > out.txt && echo run >> runs.txt && echo 1.0"
+                    cwd = "."
+                    sources = ["src/*.txt"]
+                    artifacts = ["out.txt"]
+                    outputs = { version = "stdout" }
+
+                    [tasks.report]
+                    command = "echo {{tasks.build.outputs.version}} > version.txt"
+                    cwd = "."
+                    dependencies = ["build"]
+                    "#,
+                )
+                .unwrap();
+                let mut runner = Runner::new(
+                    workflow,
+                    plugins::manager::PluginManager::new(PathBuf::new()),
+                );
+                runner.set_base_dir(dir.clone());
+                runner.set_fingerprints_dir(dir.join("fingerprints"));
+                runner.set_force(force);
+                runner.plan().unwrap();
+                let result = Arc::new(runner).run_all().await;
+                assert!(result.success());
+                assert_eq!(
+                    std::fs::read_to_string(dir.join("version.txt")).unwrap(),
+                    "1.0\n"
+                );
+                result.tasks[0].status
+            }
+        };
+
+        assert_eq!(run(false).await, TaskStatus::Success);
+        assert_eq!(run(false).await, TaskStatus::UpToDate);
+        std::fs::write(dir.join("src/a.txt"), "b").unwrap();
+        assert_eq!(run(false).await, TaskStatus::Success);
+        assert_eq!(run(true).await, TaskStatus::Success);
+        std::fs::remove_file(dir.join("out.txt")).unwrap();
+        assert_eq!(run(false).await, TaskStatus::Success);
+        assert_eq!(run(false).await, TaskStatus::UpToDate);
+        assert_eq!(
+            std::fs::read_to_string(dir.join("runs.txt")).unwrap(),
+            "run\n".repeat(4)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_params() {
+        let workflow = || -> WorkFlow {
+            toml::from_str(
+                r#"
+                name = "test"
+                version = ">=0.1.0"
+
+                [params]
+                env = { type = "enum", values = ["staging", "prod"], required = true }
+                replicas = { type = "int", default = 2 }
+
+                [tasks.deploy]
+                command = "echo deploying {{params.replicas}} to {{params.env}}"
+                when = "params.env == 'prod'"
+                "#,
+            )
+            .unwrap()
+        };
+
+        let mut runner = Runner::new(
+            workflow(),
+            plugins::manager::PluginManager::new(PathBuf::new()),
+        );
+        assert!(matches!(
+            runner.plan(),
+            Err(Error::Validation { issues, .. }) if issues[0].scope == Scope::Param("env".to_string())
+        ));
+
+        let mut runner = Runner::new(
+            workflow(),
+            plugins::manager::PluginManager::new(PathBuf::new()),
+        );
+        runner.set_params(vec![
+            ("env".to_string(), "staging".to_string()),
+            ("replicas".to_string(), "+3".to_string()),
+            ("env".to_string(), "prod".to_string()),
+        ]);
+        runner.plan().unwrap();
+        assert!(runner
+            .describe_plan()
+            .contains("    command: echo deploying 3 to prod\n"));
+        let result = Arc::new(runner).run_all().await;
+        assert_eq!(result.tasks[0].status, TaskStatus::Success);
+    }
+
+    #[tokio::test]
+    async fn test_matrix() {
+        let dir = std::env::temp_dir().join("xtomate-test-matrix");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut workflow: WorkFlow = toml::from_str(&format!(
+            r#"
+            name = "test"
+            version = ">=0.1.0"
+
+            [tasks.build]
+            command = "touch {0}/{{{{matrix.target}}}}-{{{{matrix.profile}}}}"
+            matrix = {{ target = ["x86_64", "aarch64"], profile = ["debug", "release"] }}
+
+            [tasks.package]
+            command = "ls {0} | sort | tr '\n' ' ' > {0}.package"
+            dependencies = ["build"]
+            "#,
+            dir.display()
+        ))
+        .unwrap();
+        workflow.expand_matrices().unwrap();
+
+        let mut runner = Runner::new(
+            workflow,
+            plugins::manager::PluginManager::new(PathBuf::new()),
+        );
+        runner.plan().unwrap();
+        assert_eq!(runner.order[0].len(), 4);
+        assert_eq!(runner.order[1], vec!["package"]);
+
+        let result = Arc::new(runner).run_all().await;
+        assert!(result.success());
+        assert_eq!(
+            std::fs::read_to_string(dir.with_extension("package")).unwrap(),
+            "aarch64-debug aarch64-release x86_64-debug x86_64-release "
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resource_pool() {
+        let dir = std::env::temp_dir().join("xtomate-test-resource-pool");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // Both tasks fail if they hold the lock directory at the same time
+        let command = format!(
+            "mkdir {0}/lock && sleep 0.2 && rmdir {0}/lock",
+            dir.display()
+        );
+        let workflow: WorkFlow = toml::from_str(&format!(
+            r#"
+            name = "test"
+            version = ">=0.1.0"
+
+            [resources]
+            lock = 1
+
+            [tasks.first]
+            command = "{0}"
+            resources = ["lock"]
+
+            [tasks.second]
+            command = "{0}"
+            resources = ["lock"]
+            "#,
+            command
+        ))
+        .unwrap();
+
+        let mut runner = Runner::new(
+            workflow,
+            plugins::manager::PluginManager::new(PathBuf::new()),
+        );
+        runner.plan().unwrap();
+        assert_eq!(runner.order, vec![vec!["first", "second"]]);
+
+        let result = Arc::new(runner).run_all().await;
+        assert!(result.success());
+    }
+
+    #[tokio::test]
+    async fn test_run_all_ready_queue() {
+        let dir = std::env::temp_dir().join("xtomate-test-ready-queue");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // `report` only depends on the fast task, so it must not wait for the slow one
+        let workflow: WorkFlow = toml::from_str(&format!(
+            r#"
+            name = "test"
+            version = ">=0.1.0"
+
+            [tasks.slow]
+            command = "sleep 0.5 && touch {0}/slow"
+
+            [tasks.fast]
+            command = "true"
+
+            [tasks.report]
+            command = "test ! -e {0}/slow"
+            dependencies = ["fast"]
+            "#,
+            dir.display()
+        ))
+        .unwrap();
+
+        let mut runner = Runner::new(
+            workflow,
+            plugins::manager::PluginManager::new(PathBuf::new()),
+        );
+        runner.plan().unwrap();
+
+        let result = Arc::new(runner).run_all().await;
+        assert!(result.success());
+        assert!(dir.join("slow").exists());
+    }
+
+    #[tokio::test]
+    async fn test_run_all_timeout() {
+        let workflow: WorkFlow = toml::from_str(
+            r#"
+            name = "test"
+            version = ">=0.1.0"
+
+            [tasks.hang]
+            command = "sleep 10"
+            timeout = "200ms"
+            on_error = ["cleanup"]
+
+            [tasks.cleanup]
+            run = false
+            command = "true"
+
+            [tasks.report]
+            command = "true"
+            dependencies = [{"hang" = "timeout"}]
+            "#,
+        )
+        .unwrap();
+
+        let mut runner = Runner::new(
+            workflow,
+            plugins::manager::PluginManager::new(PathBuf::new()),
+        );
+        runner.plan().unwrap();
+        let runner = Arc::new(runner);
+
+        let result = Arc::clone(&runner).run_all().await;
+        assert_eq!(result.tasks[0].name, "hang");
+        assert_eq!(result.tasks[0].status, TaskStatus::TimedOut);
+        assert_eq!(result.tasks[1].status, TaskStatus::Success);
+        assert_eq!(runner.status("cleanup"), Some(TaskStatus::Success));
+    }
+
+    #[tokio::test]
+    async fn test_failure_policy() {
+        let source = r#"
+            name = "test"
+            version = ">=0.1.0"
+            on_failure = "stop"
+
+            [tasks.broken]
+            command = "exit 1"
+
+            [tasks.slow]
+            command = "sleep 0.3"
+
+            [tasks.after]
+            command = "true"
+            dependencies = ["slow"]
+            "#;
+
+        for (keep_going, after) in [(false, TaskStatus::NotRun), (true, TaskStatus::Success)] {
+            let mut runner = Runner::new(
+                toml::from_str(source).unwrap(),
+                plugins::manager::PluginManager::new(PathBuf::new()),
+            );
+            runner.set_keep_going(keep_going);
+            runner.plan().unwrap();
+
+            let result = Arc::new(runner).run_all().await;
+            let statuses: Vec<(&str, TaskStatus)> = result
+                .tasks
+                .iter()
+                .map(|t| (t.name.as_str(), t.status))
+                .collect();
+            assert_eq!(
+                statuses,
+                vec![
+                    ("broken", TaskStatus::Failed),
+                    ("slow", TaskStatus::Success),
+                    ("after", after)
+                ]
+            );
+        }
+    }
 
-        runner.run_all().await;
+    #[test]
+    fn test_plan_version_mismatch() {
+        let mut runner = Runner::new(
+            WorkFlow::new("test".to_string(), "<0.0.1".to_string(), None),
+            plugins::manager::PluginManager::new(PathBuf::new()),
+        );
+        assert!(runner.plan().is_err());
     }
 
     #[test]
@@ -953,13 +2403,10 @@ mod tests {
             Some(vec![Dependency::Simple("task1".to_string())]),
         );
 
-        let mut runner = Runner {
+        let mut runner = Runner::new(
             workflow,
-            tasks: HashMap::new(),
-            order: vec![],
-            plugin_manager: plugins::manager::PluginManager::new(PathBuf::new()),
-            plugins: vec![],
-        };
+            plugins::manager::PluginManager::new(PathBuf::new()),
+        );
 
         runner.determine_order().unwrap();
         assert_eq!(
@@ -988,13 +2435,10 @@ mod tests {
             Some(vec![Dependency::Simple("task3".to_string())]),
         );
 
-        let mut runner = Runner {
+        let mut runner = Runner::new(
             workflow,
-            tasks: HashMap::new(),
-            order: vec![],
-            plugin_manager: plugins::manager::PluginManager::new(PathBuf::new()),
-            plugins: vec![],
-        };
+            plugins::manager::PluginManager::new(PathBuf::new()),
+        );
 
         let result = runner.determine_order();
         assert_eq!(
@@ -1002,4 +2446,105 @@ mod tests {
             Err("Cycle detected in task dependencies".to_string())
         );
     }
+
+    #[test]
+    fn test_determine_order_targets() {
+        let workflow: WorkFlow = toml::from_str(
+            r#"
+            name = "test"
+            version = "0.1.0"
+
+            [tasks.prep]
+            run = false
+            command = "echo prep"
+
+            [tasks.build]
+            command = "echo build"
+            dependencies = ["prep"]
+
+            [tasks.test]
+            command = "echo test"
+            dependencies = [{"build" = "success"}]
+
+            [tasks.lint]
+            command = "echo lint"
+            "#,
+        )
+        .unwrap();
+
+        let mut runner = Runner::new(
+            workflow,
+            plugins::manager::PluginManager::new(PathBuf::new()),
+        );
+
+        runner.determine_order().unwrap();
+        assert_eq!(
+            runner.order,
+            vec![
+                vec!["lint".to_string(), "prep".to_string()],
+                vec!["build".to_string()],
+                vec!["test".to_string()]
+            ]
+        );
+
+        runner.set_targets(vec!["build".to_string()]);
+        runner.determine_order().unwrap();
+        assert_eq!(
+            runner.order,
+            vec![vec!["prep".to_string()], vec!["build".to_string()]]
+        );
+
+        runner.set_targets(vec!["missing".to_string()]);
+        assert_eq!(
+            runner.determine_order(),
+            Err("Unknown task: missing".to_string())
+        );
+    }
+
+    #[test]
+    fn test_describe_plan() {
+        let workflow: WorkFlow = toml::from_str(
+            r#"
+            name = "test"
+            version = ">=0.1.0"
+
+            [[templates]]
+            name = "echo"
+            command = "echo {{MESSAGE}} from {{XTOMATE_TASK}}"
+            env = { LEVEL = "info" }
+
+            [tasks.greet]
+            template = "echo"
+            config = { MESSAGE = "hello" }
+            on_error = [{"plugin:logger" = { message = "{{XTOMATE_TASK}} failed" }}]
+
+            [tasks.notify]
+            plugin = "notify_send"
+            config = { message = "{{XTOMATE_WORKFLOW}} done" }
+            dependencies = ["greet"]
+            "#,
+        )
+        .unwrap();
+
+        let mut runner = Runner::new(
+            workflow,
+            plugins::manager::PluginManager::new(PathBuf::new()),
+        );
+        runner.plan().unwrap();
+
+        assert_eq!(
+            runner.describe_plan(),
+            r#"Workflow `test`
+Stage 1:
+  greet
+    command: echo hello from greet
+    env: LEVEL=info
+    on_error (if the task fails): plugin logger {"message":"greet failed"}
+Stage 2:
+  notify
+    plugin: notify_send
+    config: {"message":"test done"}
+"#
+        );
+    }
 }
@@ -0,0 +1,403 @@
+use colored::*;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::process::Stream;
+use super::result::{RunResult, TaskStatus};
+
+/// The log directory of one `xt run`: its metadata in `run.json`, and the stdout, stderr and
+/// retries of every task in `<task>.stdout.log`, `<task>.stderr.log` and `<task>.retries.log`.
+pub struct RunLog {
+    dir: PathBuf,
+    metadata: Mutex<RunMetadata>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RunMetadata {
+    pub id: String,
+    pub workflow: String,
+    /// The SHA-256 of the workflow file the run was started from. Not set if the file couldn't
+    /// be read again to hash it.
+    pub workflow_hash: Option<String>,
+    pub started_at: u64,
+    /// Not set while the run is going on, or if `xt` was killed before it could finish.
+    pub finished_at: Option<u64>,
+    pub exit_code: Option<i32>,
+    pub interrupted: bool,
+    pub tasks: Vec<TaskRecord>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TaskRecord {
+    pub name: String,
+    pub status: TaskStatus,
+    pub attempts: Vec<AttemptRecord>,
+    /// How long the task ran, including retry delays. Not set if it didn't run.
+    pub duration_ms: Option<u64>,
+    /// The exit code of the task's last attempt.
+    pub exit_code: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AttemptRecord {
+    pub status: TaskStatus,
+    pub exit_code: Option<i32>,
+    pub duration_ms: u64,
+}
+
+/// The stdout and stderr log files of a task, open for appending.
+pub struct TaskLog {
+    stdout: Mutex<File>,
+    stderr: Mutex<File>,
+}
+
+impl RunLog {
+    /// Creates the directory of a new run of `workflow` under `runs_dir`.
+    pub fn create(
+        runs_dir: &Path,
+        workflow: &str,
+        workflow_hash: Option<String>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let workflow_dir = runs_dir.join(file_name(workflow));
+        fs::create_dir_all(&workflow_dir)?;
+
+        let started_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let timestamp = chrono::Local::now().format("%Y%m%d-%H%M%S").to_string();
+        let mut id = timestamp.clone();
+        let mut suffix = 1;
+        while workflow_dir.join(&id).exists() {
+            suffix += 1;
+            id = format!("{}-{}", timestamp, suffix);
+        }
+        let dir = workflow_dir.join(&id);
+        fs::create_dir(&dir)?;
+
+        let log = RunLog {
+            dir,
+            metadata: Mutex::new(RunMetadata {
+                id,
+                workflow: workflow.to_string(),
+                workflow_hash,
+                started_at,
+                finished_at: None,
+                exit_code: None,
+                interrupted: false,
+                tasks: vec![],
+            }),
+        };
+        log.save()?;
+        Ok(log)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn id(&self) -> String {
+        self.metadata
+            .lock()
+            .expect("Failed to lock mutex")
+            .id
+            .clone()
+    }
+
+    /// Opens the stdout and stderr logs of a task.
+    pub fn task_log(&self, task: &str) -> std::io::Result<TaskLog> {
+        Ok(TaskLog {
+            stdout: Mutex::new(append(&log_path(&self.dir, task, "stdout"))?),
+            stderr: Mutex::new(append(&log_path(&self.dir, task, "stderr"))?),
+        })
+    }
+
+    /// Adds a line to the retries log of a task.
+    pub fn log_retry(&self, task: &str, message: &str) -> std::io::Result<()> {
+        writeln!(
+            append(&log_path(&self.dir, task, "retries"))?,
+            "{}",
+            message
+        )
+    }
+
+    /// Records how the run finished.
+    pub fn finish(&self, result: &RunResult) -> Result<(), Box<dyn std::error::Error>> {
+        {
+            let mut metadata = self.metadata.lock().expect("Failed to lock mutex");
+            metadata.finished_at = Some(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs());
+            metadata.exit_code = Some(result.exit_status().code());
+            metadata.interrupted = result.interrupted;
+            metadata.tasks = result
+                .tasks
+                .iter()
+                .map(|task| TaskRecord {
+                    name: task.name.clone(),
+                    status: task.status,
+                    attempts: task
+                        .attempts
+                        .iter()
+                        .map(|attempt| AttemptRecord {
+                            status: attempt.status,
+                            exit_code: attempt.exit_code,
+                            duration_ms: attempt.duration.as_millis() as u64,
+                        })
+                        .collect(),
+                    duration_ms: task.duration.map(|duration| duration.as_millis() as u64),
+                    exit_code: task.attempts.last().and_then(|attempt| attempt.exit_code),
+                })
+                .collect();
+        }
+        self.save()
+    }
+
+    fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let metadata = self.metadata.lock().expect("Failed to lock mutex");
+        fs::write(
+            self.dir.join("run.json"),
+            serde_json::to_string_pretty(&*metadata)?,
+        )?;
+        Ok(())
+    }
+}
+
+impl TaskLog {
+    pub fn write(&self, stream: Stream, line: &[u8]) -> std::io::Result<()> {
+        let file = match stream {
+            Stream::Stdout => &self.stdout,
+            Stream::Stderr => &self.stderr,
+        };
+        file.lock().expect("Failed to lock mutex").write_all(line)
+    }
+}
+
+/// Every recorded run of `workflow`, or of every workflow if it is `None`, oldest first.
+pub fn list_runs(
+    runs_dir: &Path,
+    workflow: Option<&str>,
+) -> Result<Vec<RunMetadata>, Box<dyn std::error::Error>> {
+    let workflow_dirs: Vec<PathBuf> = match workflow {
+        Some(workflow) => {
+            let workflow_dir = runs_dir.join(file_name(workflow));
+            if !workflow_dir.is_dir() {
+                return Err(
+                    format!("No runs of workflow `{}` have been recorded", workflow).into(),
+                );
+            }
+            vec![workflow_dir]
+        }
+        None => fs::read_dir(runs_dir)
+            .map_err(|_| "No runs have been recorded")?
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| path.is_dir())
+            .collect(),
+    };
+    let mut runs = vec![];
+    for workflow_dir in workflow_dirs {
+        for entry in fs::read_dir(workflow_dir)? {
+            match read_metadata(&entry?.path()) {
+                Some(Ok(run)) => runs.push(run),
+                // A run whose record can't be read (say because `xt` was killed while writing
+                // it) shouldn't hide every other run
+                Some(Err(message)) => eprintln!("{} {}", "warning:".yellow().bold(), message),
+                None => {}
+            }
+        }
+    }
+    runs.sort_by(|a, b| (a.started_at, &a.id).cmp(&(b.started_at, &b.id)));
+    Ok(runs)
+}
+
+/// Reads the metadata of the run in `run_dir`, or `None` if it isn't a run directory.
+fn read_metadata(run_dir: &Path) -> Option<Result<RunMetadata, String>> {
+    let path = run_dir.join("run.json");
+    let metadata = fs::read_to_string(&path).ok()?;
+    Some(
+        serde_json::from_str(&metadata)
+            .map_err(|e| format!("skipping unreadable run record `{}`: {}", path.display(), e)),
+    )
+}
+
+/// Finds a recorded run by its id, or the most recent run for `latest`, among the runs of
+/// `workflow` or, if it is `None`, of every workflow.
+pub fn find_run(
+    runs_dir: &Path,
+    workflow: Option<&str>,
+    id: &str,
+) -> Result<(RunMetadata, PathBuf), Box<dyn std::error::Error>> {
+    let runs = list_runs(runs_dir, workflow)?;
+    let mut matching: Vec<&RunMetadata> = if id == "latest" {
+        runs.last().into_iter().collect()
+    } else {
+        runs.iter().filter(|run| run.id == id).collect()
+    };
+    if matching.len() > 1 {
+        let workflows: Vec<&str> = matching.iter().map(|run| run.workflow.as_str()).collect();
+        return Err(format!(
+            "Workflows {} all have a run `{}`; choose one with --workflow",
+            workflows.join(", "),
+            id
+        )
+        .into());
+    }
+    let run = matching.pop().ok_or_else(|| match workflow {
+        Some(workflow) => format!("Workflow `{}` has no run `{}`", workflow, id),
+        None => format!("No run `{}` has been recorded", id),
+    })?;
+    let dir = runs_dir.join(file_name(&run.workflow)).join(&run.id);
+    Ok((run.clone(), dir))
+}
+
+/// The path of one of a task's logs (`stdout`, `stderr` or `retries`) in a run directory.
+pub fn log_path(run_dir: &Path, task: &str, kind: &str) -> PathBuf {
+    run_dir.join(format!("{}.{}.log", file_name(task), kind))
+}
+
+/// `name` as a file name: characters that don't belong in one (and a leading `.`) are
+/// percent-encoded, like `%20` for a space, so different names never share a file.
+pub(super) fn file_name(name: &str) -> String {
+    let mut file_name = String::new();
+    for (index, c) in name.char_indices() {
+        if c.is_ascii_alphanumeric() || "-_,=[]+@".contains(c) || (c == '.' && index > 0) {
+            file_name.push(c);
+        } else {
+            let mut bytes = [0; 4];
+            for byte in c.encode_utf8(&mut bytes).bytes() {
+                file_name.push_str(&format!("%{:02X}", byte));
+            }
+        }
+    }
+    file_name
+}
+
+fn append(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workflow::result::{Attempt, TaskResult};
+    use std::time::Duration;
+
+    #[test]
+    fn test_run_log() {
+        let runs_dir = std::env::temp_dir().join("xtomate-test-runs");
+        let _ = fs::remove_dir_all(&runs_dir);
+
+        let first = RunLog::create(&runs_dir, "nightly backup", None).unwrap();
+        let log = RunLog::create(&runs_dir, "nightly backup", Some("abc".to_string())).unwrap();
+        assert_ne!(first.id(), log.id());
+        assert!(log.dir().starts_with(runs_dir.join("nightly%20backup")));
+
+        let task_log = log.task_log("build").unwrap();
+        task_log.write(Stream::Stdout, b"compiling\n").unwrap();
+        task_log.write(Stream::Stderr, b"warning\n").unwrap();
+        log.log_retry("build", "attempt 1/2 failed").unwrap();
+        log.finish(&RunResult {
+            tasks: vec![TaskResult {
+                name: "build".to_string(),
+                status: TaskStatus::Success,
+                attempts: vec![
+                    Attempt {
+                        status: TaskStatus::Failed,
+                        exit_code: Some(1),
+                        duration: Duration::from_millis(20),
+                    },
+                    Attempt {
+                        status: TaskStatus::Success,
+                        exit_code: Some(0),
+                        duration: Duration::from_millis(30),
+                    },
+                ],
+                duration: Some(Duration::from_millis(1050)),
+            }],
+            interrupted: false,
+        })
+        .unwrap();
+
+        let runs = list_runs(&runs_dir, Some("nightly backup")).unwrap();
+        assert_eq!(runs.len(), 2);
+        let (run, dir) = find_run(&runs_dir, Some("nightly backup"), "latest").unwrap();
+        assert_eq!(run.id, log.id());
+        assert_eq!(run.workflow_hash.as_deref(), Some("abc"));
+        assert_eq!(run.exit_code, Some(0));
+        assert_eq!(run.tasks[0].attempts.len(), 2);
+        assert_eq!(run.tasks[0].duration_ms, Some(1050));
+        assert_eq!(run.tasks[0].exit_code, Some(0));
+        assert_eq!(
+            fs::read_to_string(log_path(&dir, "build", "stdout")).unwrap(),
+            "compiling\n"
+        );
+        assert_eq!(
+            fs::read_to_string(log_path(&dir, "build", "retries")).unwrap(),
+            "attempt 1/2 failed\n"
+        );
+
+        assert!(find_run(&runs_dir, Some("nightly backup"), "missing").is_err());
+        assert!(list_runs(&runs_dir, Some("other")).is_err());
+    }
+
+    #[test]
+    fn test_file_name() {
+        assert_eq!(file_name("build[target=x86_64]"), "build[target=x86_64]");
+        assert_eq!(file_name("nightly backup"), "nightly%20backup");
+        assert_eq!(file_name("a/b"), "a%2Fb");
+        assert_eq!(file_name(".."), "%2E.");
+        assert_eq!(file_name("100%"), "100%25");
+        assert_eq!(file_name("déjà"), "d%C3%A9j%C3%A0");
+        assert_ne!(file_name("nightly backup"), file_name("nightly_backup"));
+        assert_ne!(file_name("a b"), file_name("a%20b"));
+    }
+
+    #[test]
+    fn test_runs_of_every_workflow() {
+        let runs_dir = std::env::temp_dir().join("xtomate-test-history");
+        let _ = fs::remove_dir_all(&runs_dir);
+        assert!(list_runs(&runs_dir, None).is_err());
+
+        let backup = RunLog::create(&runs_dir, "backup", None).unwrap();
+        let deploy = RunLog::create(&runs_dir, "deploy", None).unwrap();
+        let runs = list_runs(&runs_dir, None).unwrap();
+        let workflows: Vec<&str> = runs.iter().map(|run| run.workflow.as_str()).collect();
+        assert_eq!(workflows.len(), 2);
+        assert!(workflows.contains(&"backup") && workflows.contains(&"deploy"));
+
+        // Runs started in the same second in different workflows get the same id
+        if backup.id() == deploy.id() {
+            assert!(find_run(&runs_dir, None, &backup.id())
+                .unwrap_err()
+                .to_string()
+                .ends_with("choose one with --workflow"));
+        } else {
+            let (run, dir) = find_run(&runs_dir, None, &backup.id()).unwrap();
+            assert_eq!(run.workflow, "backup");
+            assert_eq!(dir, backup.dir());
+        }
+        let (run, dir) = find_run(&runs_dir, Some("deploy"), &deploy.id()).unwrap();
+        assert_eq!(run.workflow, "deploy");
+        assert_eq!(dir, deploy.dir());
+        assert!(find_run(&runs_dir, None, "missing").is_err());
+    }
+
+    #[test]
+    fn test_unreadable_runs() {
+        let runs_dir = std::env::temp_dir().join("xtomate-test-run-records");
+        let _ = fs::remove_dir_all(&runs_dir);
+        let log = RunLog::create(&runs_dir, "backup", None).unwrap();
+
+        // A record cut short when `xt` was killed
+        let workflow_dir = runs_dir.join("backup");
+        fs::create_dir_all(workflow_dir.join("truncated")).unwrap();
+        fs::write(workflow_dir.join("truncated/run.json"), "{\"id\": \"trunc").unwrap();
+
+        let ids: Vec<String> = list_runs(&runs_dir, Some("backup"))
+            .unwrap()
+            .into_iter()
+            .map(|run| run.id)
+            .collect();
+        assert_eq!(ids, vec![log.id()]);
+        assert!(find_run(&runs_dir, Some("backup"), &log.id()).is_ok());
+    }
+}
@@ -2,26 +2,74 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use toml::Table;
 
+use super::condition::Expr;
+use super::duration::DurationValue;
+use super::matrix;
+use super::placeholders::output_references;
+use super::validate::{Issue, Scope};
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct WorkFlow {
     pub name: String,
     pub version: String,
     pub description: Option<String>,
+    /// How many tasks may run at the same time (unlimited if not set).
+    pub max_parallel: Option<usize>,
+    /// How long the whole workflow may run before its running tasks are killed, e.g. `1h`.
+    pub timeout: Option<String>,
+    /// How long commands get to exit after SIGTERM or Ctrl-C before they are killed.
+    pub grace_period: Option<String>,
+    /// What happens to the rest of the workflow when a task fails.
+    pub on_failure: Option<FailurePolicy>,
+    /// How the output of running commands is printed.
+    pub output: Option<OutputMode>,
     on_start: Option<Vec<Dependency>>,
     on_finish: Option<Vec<Dependency>>,
     tasks: HashMap<String, Task>,
     plugins: Option<Vec<Plugin>>,
     templates: Option<Vec<TaskTemplate>>,
+    /// Named resource pools and how many tasks may hold each of them at once.
+    resources: Option<HashMap<String, usize>>,
+    /// Inputs of the workflow, set with `xt run --param NAME=VALUE`.
+    params: Option<HashMap<String, Param>>,
+    /// The instances every matrix task was expanded into.
+    #[serde(skip)]
+    matrices: HashMap<String, Vec<String>>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Task {
     pub command: Option<String>,
+    /// A program and its arguments, run without a shell.
+    pub exec: Option<Vec<String>>,
+    /// The shell that runs `command` (`sh` if not set).
+    pub shell: Option<Shell>,
+    /// The directory the command runs in, relative to the workflow file.
+    pub cwd: Option<String>,
     pub template: Option<String>,
-    pub retry: Option<usize>,
-    pub retry_delay: Option<usize>,
+    #[serde(flatten)]
+    pub retry_options: RetryOptions,
+    /// How long the command may run before it is killed, e.g. `10m`.
+    pub timeout: Option<String>,
     pub run: Option<bool>,
     pub plugin: Option<String>,
+    /// Resource pools the task holds a slot in while it runs.
+    pub resources: Option<Vec<String>>,
+    /// Values other tasks can use as `{{tasks.NAME.outputs.OUTPUT}}` once this task succeeds.
+    pub outputs: Option<HashMap<String, OutputSource>>,
+    /// Globs of the files the task reads. If none of them, nor the command, have changed
+    /// since the task last succeeded, it isn't run again.
+    pub sources: Option<Vec<String>>,
+    /// Globs of the files the task creates, which have to exist for it to be up to date.
+    pub artifacts: Option<Vec<String>>,
+    /// Only run the task if this expression is true, e.g. `env.CI == 'true'`.
+    pub when: Option<String>,
+    /// Run the task once for every combination of these values, available as
+    /// `{{matrix.NAME}}`, e.g. `{ target = ["x86_64", "aarch64"] }`.
+    pub matrix: Option<Table>,
+    /// The values of the matrix instance this task is, set by `WorkFlow::expand_matrices`.
+    #[serde(skip)]
+    pub matrix_values: Vec<(String, String)>,
     on_start: Option<Vec<Dependency>>,
     on_finish: Option<Vec<Dependency>>,
     on_error: Option<Vec<Dependency>>,
@@ -34,14 +82,127 @@ pub struct Task {
 pub struct TaskTemplate {
     pub name: String,
     pub command: Option<String>,
-    pub retry: Option<usize>,
-    pub retry_delay: Option<usize>,
+    pub exec: Option<Vec<String>>,
+    pub shell: Option<Shell>,
+    pub cwd: Option<String>,
+    #[serde(flatten)]
+    pub retry_options: RetryOptions,
+    pub timeout: Option<String>,
     pub run: Option<bool>,
+    pub when: Option<String>,
     pub env: Option<Table>,
     pub dependencies: Option<Vec<Dependency>>,
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+/// How a failed command is retried. Tasks using a template fall back to the template's
+/// settings for anything they don't set themselves.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct RetryOptions {
+    /// How many times a failed command is run again.
+    pub retry: Option<usize>,
+    /// How long to wait before the first retry, in seconds or as a duration like `500ms`.
+    pub retry_delay: Option<DurationValue>,
+    /// How the delay grows between retries.
+    pub retry_backoff: Option<Backoff>,
+    /// The longest the delay may grow to.
+    pub retry_max_delay: Option<DurationValue>,
+    /// Up to this much random extra delay is added to every retry.
+    pub retry_jitter: Option<DurationValue>,
+    /// Only retry when the command exits with one of these codes.
+    pub retry_exit_codes: Option<Vec<i32>>,
+    /// Never retry when the command exits with one of these codes.
+    pub no_retry_exit_codes: Option<Vec<i32>>,
+    /// Only retry when the command's stderr matches this regular expression.
+    pub retry_stderr: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Backoff {
+    /// Wait `retry_delay` before every retry.
+    #[default]
+    Fixed,
+    /// Wait `retry_delay` times the number of the retry.
+    Linear,
+    /// Double the delay after every retry.
+    Exponential,
+}
+
+/// A named input of the workflow, available as `{{params.NAME}}`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Param {
+    #[serde(rename = "type", default)]
+    pub kind: ParamType,
+    pub default: Option<toml::Value>,
+    pub description: Option<String>,
+    /// Whether the run fails if the parameter isn't given and has no default.
+    pub required: Option<bool>,
+    /// The values an `enum` parameter may have.
+    pub values: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ParamType {
+    #[default]
+    String,
+    Int,
+    Bool,
+    /// One of the parameter's `values`.
+    Enum,
+}
+
+/// The shell a command is run with.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Shell {
+    /// A program that runs the command given after `-c`, like `bash`, `zsh` or `python3`.
+    Name(String),
+    /// The program and arguments the command is appended to, e.g. `["node", "-e"]`.
+    Argv(Vec<String>),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum FailurePolicy {
+    /// Don't start any more tasks once a task has failed.
+    Stop,
+    /// Keep running every task that doesn't depend on the failed one.
+    #[default]
+    Continue,
+}
+
+/// Where the value of a task output comes from.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum OutputSource {
+    /// Everything the command printed to `"stdout"` or `"stderr"`.
+    Stream(OutputStream),
+    /// The first capture group (or the whole match, if it has none) of a regular expression
+    /// in stdout.
+    Regex { regex: String },
+    /// The contents of a file.
+    File { file: String },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputMode {
+    /// Print every line as soon as it is written, prefixed with the task's name.
+    #[default]
+    Stream,
+    /// Print each task's output in one block once its command has finished.
+    Grouped,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(untagged)]
 pub enum Dependency {
     Simple(String),
@@ -62,11 +223,19 @@ impl WorkFlow {
             name,
             version,
             description,
+            max_parallel: None,
+            timeout: None,
+            grace_period: None,
+            on_failure: None,
+            output: None,
             on_finish: None,
             on_start: None,
             tasks: HashMap::new(),
             plugins: None,
             templates: None,
+            resources: None,
+            params: None,
+            matrices: HashMap::new(),
         }
     }
 
@@ -80,12 +249,22 @@ impl WorkFlow {
             name,
             Task {
                 command: Some(command),
+                exec: None,
+                shell: None,
+                cwd: None,
                 plugin: None,
                 template: None,
                 config: None,
                 run: None,
-                retry: None,
-                retry_delay: None,
+                retry_options: RetryOptions::default(),
+                timeout: None,
+                resources: None,
+                outputs: None,
+                sources: None,
+                artifacts: None,
+                when: None,
+                matrix: None,
+                matrix_values: vec![],
                 env: None,
                 dependencies,
                 on_start: None,
@@ -116,12 +295,173 @@ impl WorkFlow {
     }
 
     pub fn get_template(&self, name: &str) -> Option<&TaskTemplate> {
-        self.templates
-            .as_ref()
-            .clone()
-            .expect("No templates defined")
+        self.templates.as_ref()?.iter().find(|t| t.name == name)
+    }
+
+    pub fn get_templates(&self) -> Option<&Vec<TaskTemplate>> {
+        self.templates.as_ref()
+    }
+
+    pub fn get_resources(&self) -> Option<&HashMap<String, usize>> {
+        self.resources.as_ref()
+    }
+
+    pub fn get_params(&self) -> Option<&HashMap<String, Param>> {
+        self.params.as_ref()
+    }
+
+    /// The instances a matrix task was expanded into, or `None` if `name` isn't a matrix task.
+    pub fn matrix_instances(&self, name: &str) -> Option<&Vec<String>> {
+        self.matrices.get(name)
+    }
+
+    /// Replaces every task with a `matrix` by one task per combination of its values, named
+    /// like `build[profile=debug,target=x86_64]`, and makes dependencies and hooks on the
+    /// original task refer to all of its instances.
+    pub fn expand_matrices(&mut self) -> Result<(), Vec<Issue>> {
+        let mut issues = vec![];
+        let mut names: Vec<String> = self
+            .tasks
             .iter()
-            .find(|t| t.name == name)
+            .filter(|(_, task)| task.matrix.is_some())
+            .map(|(name, _)| name.clone())
+            .collect();
+        names.sort();
+        for name in names {
+            let scope = Scope::Task(name.clone());
+            let combinations =
+                match matrix::combinations(self.tasks[&name].matrix.as_ref().unwrap()) {
+                    Ok(combinations) => combinations,
+                    Err(e) => {
+                        issues.push(Issue {
+                            scope,
+                            message: format!("matrix: {}", e),
+                        });
+                        continue;
+                    }
+                };
+            let task = self.tasks.remove(&name).unwrap();
+            let mut instances = vec![];
+            for combination in combinations {
+                let instance = matrix::instance_name(&name, &combination);
+                if self.tasks.contains_key(&instance) {
+                    issues.push(Issue {
+                        scope: scope.clone(),
+                        message: format!("matrix instance `{}` is already a task", instance),
+                    });
+                    continue;
+                }
+                let mut task = task.clone();
+                task.matrix = None;
+                task.matrix_values = combination;
+                self.tasks.insert(instance.clone(), task);
+                instances.push(instance);
+            }
+            self.matrices.insert(name, instances);
+        }
+        if !issues.is_empty() {
+            return Err(issues);
+        }
+
+        let instances = &self.matrices;
+        matrix::expand_references(self.on_start.as_mut(), instances);
+        matrix::expand_references(self.on_finish.as_mut(), instances);
+        for task in self.tasks.values_mut() {
+            matrix::expand_references(task.dependencies.as_mut(), instances);
+            matrix::expand_references(task.on_start.as_mut(), instances);
+            matrix::expand_references(task.on_finish.as_mut(), instances);
+            matrix::expand_references(task.on_error.as_mut(), instances);
+        }
+        for template in self.templates.iter_mut().flatten() {
+            matrix::expand_references(template.dependencies.as_mut(), instances);
+        }
+        Ok(())
+    }
+
+    /// The `(task, output)` of every output of another task used by a task's command, `exec`,
+    /// `cwd`, environment, plugin configuration or template, sorted and without duplicates.
+    pub fn output_references(&self, task_name: &str) -> Vec<(String, String)> {
+        let Some(task) = self.get_task(task_name) else {
+            return vec![];
+        };
+        let template = task.template.as_ref().and_then(|t| self.get_template(t));
+
+        let mut texts: Vec<&str> = vec![];
+        for (command, exec, cwd) in [
+            (&task.command, &task.exec, &task.cwd),
+            template
+                .map(|t| (&t.command, &t.exec, &t.cwd))
+                .unwrap_or((&None, &None, &None)),
+        ] {
+            texts.extend(command.as_deref());
+            texts.extend(exec.iter().flatten().map(|arg| arg.as_str()));
+            texts.extend(cwd.as_deref());
+        }
+        for table in [
+            task.get_env(),
+            task.get_config(),
+            template.and_then(|t| t.get_env()),
+        ]
+        .into_iter()
+        .flatten()
+        {
+            table
+                .values()
+                .for_each(|value| collect_strings(value, &mut texts));
+        }
+
+        let mut references: Vec<(String, String)> = texts
+            .into_iter()
+            .flat_map(output_references)
+            .filter(|(task, _)| task != task_name)
+            .collect();
+        references.sort();
+        references.dedup();
+        references
+    }
+
+    /// The `when` conditions of a task and of its template.
+    pub fn conditions(&self, task_name: &str) -> Vec<&str> {
+        let Some(task) = self.get_task(task_name) else {
+            return vec![];
+        };
+        let template = task.template.as_ref().and_then(|t| self.get_template(t));
+        task.when
+            .as_deref()
+            .into_iter()
+            .chain(template.and_then(|t| t.when.as_deref()))
+            .collect()
+    }
+
+    /// The tasks whose status or outputs a task's `when` conditions use, sorted and without
+    /// duplicates. Conditions that can't be parsed are ignored.
+    pub fn condition_references(&self, task_name: &str) -> Vec<String> {
+        let mut references: Vec<String> = self
+            .conditions(task_name)
+            .into_iter()
+            .filter_map(|condition| Expr::parse(condition).ok())
+            .flat_map(|expr| {
+                expr.variables()
+                    .into_iter()
+                    .filter_map(|variable| variable.strip_prefix("tasks."))
+                    .filter_map(|variable| variable.split_once('.'))
+                    .map(|(task, _)| task.to_string())
+                    .collect::<Vec<_>>()
+            })
+            .filter(|task| task != task_name)
+            .collect();
+        references.sort();
+        references.dedup();
+        references
+    }
+}
+
+fn collect_strings<'a>(value: &'a toml::Value, strings: &mut Vec<&'a str>) {
+    match value {
+        toml::Value::String(s) => strings.push(s),
+        toml::Value::Array(values) => values.iter().for_each(|v| collect_strings(v, strings)),
+        toml::Value::Table(table) => table.values().for_each(|v| collect_strings(v, strings)),
+        _ => {}
     }
 }
 
@@ -161,6 +501,63 @@ impl TaskTemplate {
     }
 }
 
+impl Shell {
+    /// The program and arguments a command is appended to.
+    pub fn prefix(&self) -> Vec<String> {
+        match self {
+            Shell::Name(name) => vec![name.clone(), "-c".to_string()],
+            Shell::Argv(argv) => argv.clone(),
+        }
+    }
+
+    /// The program and arguments to run `command` with.
+    pub fn argv(&self, command: &str) -> Vec<String> {
+        let mut argv = self.prefix();
+        argv.push(command.to_string());
+        argv
+    }
+}
+
+impl Default for Shell {
+    fn default() -> Self {
+        Shell::Name("sh".to_string())
+    }
+}
+
+impl RetryOptions {
+    /// These options, with anything not set taken from `fallback`.
+    pub fn or(&self, fallback: &RetryOptions) -> RetryOptions {
+        RetryOptions {
+            retry: self.retry.or(fallback.retry),
+            retry_delay: self
+                .retry_delay
+                .clone()
+                .or_else(|| fallback.retry_delay.clone()),
+            retry_backoff: self.retry_backoff.or(fallback.retry_backoff),
+            retry_max_delay: self
+                .retry_max_delay
+                .clone()
+                .or_else(|| fallback.retry_max_delay.clone()),
+            retry_jitter: self
+                .retry_jitter
+                .clone()
+                .or_else(|| fallback.retry_jitter.clone()),
+            retry_exit_codes: self
+                .retry_exit_codes
+                .clone()
+                .or_else(|| fallback.retry_exit_codes.clone()),
+            no_retry_exit_codes: self
+                .no_retry_exit_codes
+                .clone()
+                .or_else(|| fallback.no_retry_exit_codes.clone()),
+            retry_stderr: self
+                .retry_stderr
+                .clone()
+                .or_else(|| fallback.retry_stderr.clone()),
+        }
+    }
+}
+
 impl Plugin {
     pub fn get_config(&self) -> Option<&Table> {
         self.config.as_ref()
@@ -195,12 +592,22 @@ mod tests {
     fn test_task() {
         let task = Task {
             command: Some("echo Hello".to_string()),
+            exec: None,
+            shell: None,
+            cwd: None,
             plugin: None,
             template: None,
             config: None,
             run: None,
-            retry: None,
-            retry_delay: None,
+            retry_options: RetryOptions::default(),
+            timeout: None,
+            resources: None,
+            outputs: None,
+            sources: None,
+            artifacts: None,
+            when: None,
+            matrix: None,
+            matrix_values: vec![],
             env: None,
             dependencies: Some(vec![Dependency::Simple("task1".to_string())]),
             on_start: None,
@@ -212,4 +619,68 @@ mod tests {
             Some(&vec![Dependency::Simple("task1".to_string())])
         );
     }
+
+    #[test]
+    fn test_expand_matrices() {
+        let mut workflow: WorkFlow = toml::from_str(
+            r#"
+            name = "test"
+            version = "0.1.0"
+
+            [tasks.build]
+            command = "cargo build --target {{matrix.target}}"
+            matrix = { target = ["x86_64", "aarch64"] }
+            on_error = ["task:notify"]
+
+            [tasks.package]
+            command = "tar czf dist.tgz target"
+            dependencies = ["build"]
+
+            [tasks.notify]
+            command = "echo failed"
+            run = false
+            "#,
+        )
+        .unwrap();
+        workflow.expand_matrices().unwrap();
+
+        let instances = vec![
+            "build[target=x86_64]".to_string(),
+            "build[target=aarch64]".to_string(),
+        ];
+        assert_eq!(workflow.matrix_instances("build"), Some(&instances));
+        assert!(workflow.get_task("build").is_none());
+        let task = workflow.get_task("build[target=aarch64]").unwrap();
+        assert_eq!(
+            task.matrix_values,
+            vec![("target".to_string(), "aarch64".to_string())]
+        );
+        assert_eq!(
+            task.get_on_error(),
+            Some(&vec![Dependency::Simple("task:notify".to_string())])
+        );
+        assert_eq!(
+            workflow.get_task("package").unwrap().get_dependencies(),
+            Some(&instances.into_iter().map(Dependency::Simple).collect())
+        );
+
+        let mut workflow: WorkFlow = toml::from_str(
+            r#"
+            name = "test"
+            version = "0.1.0"
+
+            [tasks.build]
+            command = "make"
+            matrix = { target = [] }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            workflow.expand_matrices(),
+            Err(vec![Issue {
+                scope: Scope::Task("build".to_string()),
+                message: "matrix: `target` has no values".to_string(),
+            }])
+        );
+    }
 }
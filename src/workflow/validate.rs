@@ -0,0 +1,912 @@
+use semver::{Version, VersionReq};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use toml::Value;
+
+use super::condition::Expr;
+use super::duration::parse_duration;
+use super::graph::TaskGraph;
+use super::params::{parse_value, value_to_string};
+use super::retry::RetryPolicy;
+use super::runner::parse_dependency;
+use super::structure::{Dependency, OutputSource, ParamType, RetryOptions, Shell, WorkFlow};
+
+/// Statuses accepted on the right-hand side of a `{"task" = "status"}` dependency.
+pub const STATUSES: [&str; 9] = [
+    "success",
+    "up_to_date",
+    "failure",
+    "fail",
+    "timeout",
+    "skipped",
+    "condition_false",
+    "not_needed",
+    "any",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Scope {
+    Workflow,
+    Task(String),
+    Template(String),
+    Param(String),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Issue {
+    pub scope: Scope,
+    pub message: String,
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scope::Workflow => write!(f, "workflow"),
+            Scope::Task(name) => write!(f, "task `{}`", name),
+            Scope::Template(name) => write!(f, "template `{}`", name),
+            Scope::Param(name) => write!(f, "param `{}`", name),
+        }
+    }
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.scope, self.message)
+    }
+}
+
+/// Checks a workflow for problems that would otherwise only show up (usually as a panic)
+/// while it is running. Every problem found is returned, in a stable order.
+pub fn validate(workflow: &WorkFlow) -> Vec<Issue> {
+    let mut validator = Validator {
+        workflow,
+        issues: vec![],
+    };
+
+    validator.check_version();
+
+    validator.check_timeout(&Scope::Workflow, workflow.timeout.as_deref());
+    if let Some(Err(e)) = workflow.grace_period.as_deref().map(parse_duration) {
+        validator.push(&Scope::Workflow, format!("grace_period: {}", e));
+    }
+    if workflow.max_parallel == Some(0) {
+        validator.push(
+            &Scope::Workflow,
+            "max_parallel must be at least 1".to_string(),
+        );
+    }
+    let mut pools: Vec<(&String, &usize)> =
+        workflow.get_resources().into_iter().flatten().collect();
+    pools.sort();
+    for (pool, capacity) in pools {
+        if *capacity == 0 {
+            validator.push(
+                &Scope::Workflow,
+                format!(
+                    "resource pool `{}` must have a capacity of at least 1",
+                    pool
+                ),
+            );
+        }
+    }
+
+    let mut params: Vec<_> = workflow.get_params().into_iter().flatten().collect();
+    params.sort_by_key(|(name, _)| *name);
+    for (name, param) in params {
+        let scope = Scope::Param(name.clone());
+        match (param.kind, &param.values) {
+            (ParamType::Enum, None) => validator.push(&scope, "enum has no values".to_string()),
+            (ParamType::Enum, Some(values)) if values.is_empty() => {
+                validator.push(&scope, "enum has no values".to_string())
+            }
+            (ParamType::Enum, Some(_)) | (_, None) => {}
+            (_, Some(_)) => validator.push(&scope, "values are only allowed for enums".to_string()),
+        }
+        if let Some(default) = &param.default {
+            let message = match value_to_string(default) {
+                Some(value) => parse_value(param, &value).err(),
+                None => Some("must be a string, integer or boolean".to_string()),
+            };
+            if let Some(message) = message {
+                validator.push(&scope, format!("default: {}", message));
+            }
+        }
+    }
+
+    for hook in workflow.get_on_start().unwrap_or(&vec![]) {
+        validator.check_hook(&Scope::Workflow, "on_start", hook);
+    }
+    for hook in workflow.get_on_finish().unwrap_or(&vec![]) {
+        validator.check_hook(&Scope::Workflow, "on_finish", hook);
+    }
+
+    let mut task_names: Vec<&String> = workflow.get_tasks().keys().collect();
+    task_names.sort();
+    for name in task_names {
+        validator.check_task(name);
+    }
+
+    for template in workflow.get_templates().unwrap_or(&vec![]) {
+        let scope = Scope::Template(template.name.clone());
+        if template.command.is_none() && template.exec.is_none() {
+            validator.push(&scope, "has no command or exec".to_string());
+        }
+        validator.check_program(
+            &scope,
+            template.command.as_ref(),
+            template.exec.as_ref(),
+            template.shell.as_ref(),
+        );
+        validator.check_timeout(&scope, template.timeout.as_deref());
+        validator.check_retry(&scope, &template.retry_options);
+        validator.check_condition(&scope, template.when.as_deref());
+        for dependency in template.get_dependencies().unwrap_or(&vec![]) {
+            validator.check_dependency(&scope, dependency);
+        }
+    }
+
+    validator.check_cycles();
+
+    validator.issues
+}
+
+struct Validator<'a> {
+    workflow: &'a WorkFlow,
+    issues: Vec<Issue>,
+}
+
+impl Validator<'_> {
+    fn push(&mut self, scope: &Scope, message: String) {
+        self.issues.push(Issue {
+            scope: scope.clone(),
+            message,
+        });
+    }
+
+    fn check_version(&mut self) {
+        match VersionReq::parse(&self.workflow.version) {
+            Ok(version_req) => {
+                let current = Version::parse(env!("CARGO_PKG_VERSION")).unwrap();
+                if !version_req.matches(&current) {
+                    self.push(
+                        &Scope::Workflow,
+                        format!(
+                            "requires XTomate {}, but this is version {}",
+                            self.workflow.version, current
+                        ),
+                    );
+                }
+            }
+            Err(e) => self.push(
+                &Scope::Workflow,
+                format!(
+                    "invalid version requirement `{}`: {}",
+                    self.workflow.version, e
+                ),
+            ),
+        }
+    }
+
+    fn check_timeout(&mut self, scope: &Scope, timeout: Option<&str>) {
+        if let Some(Err(e)) = timeout.map(parse_duration) {
+            self.push(scope, format!("timeout: {}", e));
+        }
+    }
+
+    fn check_retry(&mut self, scope: &Scope, options: &RetryOptions) {
+        if let Err(e) = RetryPolicy::new(options) {
+            self.push(scope, e);
+        }
+    }
+
+    fn check_program(
+        &mut self,
+        scope: &Scope,
+        command: Option<&String>,
+        exec: Option<&Vec<String>>,
+        shell: Option<&Shell>,
+    ) {
+        if command.is_some() && exec.is_some() {
+            self.push(scope, "has both command and exec".to_string());
+        }
+        if exec.is_some_and(|exec| exec.is_empty()) {
+            self.push(scope, "exec must not be empty".to_string());
+        }
+        if exec.is_some() && shell.is_some() {
+            self.push(scope, "shell has no effect with exec".to_string());
+        }
+        if shell.is_some_and(|shell| shell.prefix().first().is_none_or(|p| p.is_empty())) {
+            self.push(scope, "shell must not be empty".to_string());
+        }
+    }
+
+    fn check_condition(&mut self, scope: &Scope, condition: Option<&str>) {
+        if let Some(Err(e)) = condition.map(Expr::parse) {
+            self.push(scope, format!("when: {}", e));
+        }
+    }
+
+    fn check_globs(&mut self, scope: &Scope, field: &str, patterns: Option<&Vec<String>>) {
+        for pattern in patterns.into_iter().flatten() {
+            if let Err(e) = glob::Pattern::new(pattern) {
+                self.push(
+                    scope,
+                    format!("{}: invalid glob `{}`: {}", field, pattern, e),
+                );
+            }
+        }
+    }
+
+    fn check_outputs(&mut self, scope: &Scope, name: &str) {
+        let task = self.workflow.get_task(name).unwrap();
+        let mut outputs: Vec<_> = task.outputs.iter().flatten().collect();
+        outputs.sort_by_key(|(output, _)| *output);
+        for (output, source) in outputs {
+            if let OutputSource::Regex { regex } = source {
+                if let Err(e) = regex::Regex::new(regex) {
+                    self.push(scope, format!("output `{}`: {}", output, e));
+                }
+            }
+        }
+
+        for (producer, output) in self.workflow.output_references(name) {
+            match self.workflow.get_task(&producer) {
+                None => self.push(
+                    scope,
+                    format!("uses output `{}` of undefined task `{}`", output, producer),
+                ),
+                Some(task)
+                    if !task
+                        .outputs
+                        .as_ref()
+                        .is_some_and(|outputs| outputs.contains_key(&output)) =>
+                {
+                    self.push(
+                        scope,
+                        format!(
+                            "uses output `{}`, which task `{}` doesn't declare",
+                            output, producer
+                        ),
+                    )
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    fn check_task(&mut self, name: &str) {
+        let task = self.workflow.get_task(name).unwrap();
+        let scope = Scope::Task(name.to_string());
+
+        if task.command.is_none()
+            && task.exec.is_none()
+            && task.template.is_none()
+            && task.plugin.is_none()
+        {
+            self.push(
+                &scope,
+                "has no command, exec, template or plugin".to_string(),
+            );
+        }
+        self.check_program(
+            &scope,
+            task.command.as_ref(),
+            task.exec.as_ref(),
+            task.shell.as_ref(),
+        );
+        if let Some(template) = &task.template {
+            self.check_template_exists(&scope, template);
+        }
+        if let Some(plugin) = &task.plugin {
+            self.check_plugin_declared(&scope, plugin);
+        }
+        self.check_timeout(&scope, task.timeout.as_deref());
+        self.check_retry(&scope, &task.retry_options);
+        self.check_outputs(&scope, name);
+        self.check_globs(&scope, "sources", task.sources.as_ref());
+        self.check_globs(&scope, "artifacts", task.artifacts.as_ref());
+        if task.artifacts.is_some() && task.sources.is_none() {
+            self.push(
+                &scope,
+                "artifacts have no effect without sources".to_string(),
+            );
+        }
+        self.check_condition(&scope, task.when.as_deref());
+        for referenced in self.workflow.condition_references(name) {
+            self.check_task_exists(&scope, "when", &referenced);
+        }
+        for pool in task.resources.iter().flatten() {
+            if !self
+                .workflow
+                .get_resources()
+                .is_some_and(|pools| pools.contains_key(pool))
+            {
+                self.push(&scope, format!("uses undefined resource pool `{}`", pool));
+            }
+        }
+
+        for dependency in task.get_dependencies().unwrap_or(&vec![]) {
+            self.check_dependency(&scope, dependency);
+        }
+        for hook in task.get_on_start().unwrap_or(&vec![]) {
+            self.check_hook(&scope, "on_start", hook);
+        }
+        for hook in task.get_on_finish().unwrap_or(&vec![]) {
+            self.check_hook(&scope, "on_finish", hook);
+        }
+        for hook in task.get_on_error().unwrap_or(&vec![]) {
+            self.check_hook(&scope, "on_error", hook);
+        }
+    }
+
+    fn check_dependency(&mut self, scope: &Scope, dependency: &Dependency) {
+        match dependency {
+            Dependency::Simple(name) => self.check_task_exists(scope, "dependencies", name),
+            Dependency::Status(table) => {
+                if table.len() != 1 {
+                    self.push(
+                        scope,
+                        format!(
+                            "dependency entries must name exactly one task, found {}",
+                            table.len()
+                        ),
+                    );
+                }
+                for (name, status) in table {
+                    self.check_task_exists(scope, "dependencies", name);
+                    match status.as_str() {
+                        Some(status) if STATUSES.contains(&status) => {}
+                        Some(status) => self.push(
+                            scope,
+                            format!(
+                                "dependency on `{}` requires unknown status `{}` (expected one of: {})",
+                                name,
+                                status,
+                                STATUSES.join(", ")
+                            ),
+                        ),
+                        None => self.push(
+                            scope,
+                            format!("dependency on `{}` must have a status string", name),
+                        ),
+                    }
+                }
+            }
+        }
+    }
+
+    fn check_hook(&mut self, scope: &Scope, hook_kind: &str, hook: &Dependency) {
+        match hook {
+            Dependency::Simple(target) => self.check_hook_target(scope, hook_kind, target),
+            Dependency::Status(table) => {
+                for (target, value) in table {
+                    self.check_hook_target(scope, hook_kind, target);
+                    if let ("plugin", plugin) = parse_dependency(target) {
+                        if !matches!(value, Value::Table(_)) {
+                            self.push(
+                                scope,
+                                format!(
+                                    "{} configuration for plugin `{}` must be a table",
+                                    hook_kind, plugin
+                                ),
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn check_hook_target(&mut self, scope: &Scope, hook_kind: &str, target: &str) {
+        match parse_dependency(target) {
+            ("task", task) => self.check_task_exists(scope, hook_kind, task),
+            ("template", template) => self.check_template_exists(scope, template),
+            ("plugin", plugin) => self.check_plugin_declared(scope, plugin),
+            (kind, _) => self.push(
+                scope,
+                format!(
+                    "{} entry `{}` has unknown type `{}` (expected task, template or plugin)",
+                    hook_kind, target, kind
+                ),
+            ),
+        }
+    }
+
+    fn check_task_exists(&mut self, scope: &Scope, field: &str, name: &str) {
+        if self.workflow.get_task(name).is_none() {
+            self.push(
+                scope,
+                format!("{} references undefined task `{}`", field, name),
+            );
+        }
+    }
+
+    fn check_template_exists(&mut self, scope: &Scope, name: &str) {
+        if self.workflow.get_template(name).is_none() {
+            self.push(scope, format!("uses undefined template `{}`", name));
+        }
+    }
+
+    fn check_plugin_declared(&mut self, scope: &Scope, name: &str) {
+        let declared = self
+            .workflow
+            .get_plugins()
+            .is_some_and(|plugins| plugins.iter().any(|p| p.name == name));
+        if !declared {
+            self.push(
+                scope,
+                format!(
+                    "uses plugin `{}` which is not declared in [[plugins]]",
+                    name
+                ),
+            );
+        }
+    }
+
+    /// Looks for cycles in the same graph the runner orders tasks by: dependencies (including
+    /// those of templates), used outputs and `when` conditions.
+    fn check_cycles(&mut self) {
+        let task_graph = TaskGraph::new(self.workflow);
+        let edges: Vec<(&str, Vec<String>)> = self
+            .workflow
+            .get_tasks()
+            .keys()
+            .map(|name| {
+                let mut dependencies = task_graph.dependencies(name).unwrap_or_default();
+                dependencies.sort();
+                (name.as_str(), dependencies)
+            })
+            .collect();
+        let graph: HashMap<&str, Vec<&str>> = edges
+            .iter()
+            .map(|(name, dependencies)| (*name, dependencies.iter().map(|d| d.as_str()).collect()))
+            .collect();
+
+        let mut names: Vec<&str> = graph.keys().copied().collect();
+        names.sort();
+
+        let mut done: HashSet<&str> = HashSet::new();
+        let mut cycles: Vec<Vec<&str>> = vec![];
+        for name in names.iter().copied() {
+            let mut path = vec![];
+            find_cycles(name, &graph, &mut path, &mut done, &mut cycles);
+        }
+
+        for cycle in cycles {
+            self.push(
+                &Scope::Task(cycle[0].to_string()),
+                format!("dependency cycle: {}", cycle.join(" -> ")),
+            );
+        }
+
+        // A hook target that needs the hook's owner to finish first waits for a task that is
+        // itself waiting for the hook.
+        for name in names {
+            for (hook, target) in task_graph.hooks(name).unwrap_or_default() {
+                let message = if target == name {
+                    format!("{} hook runs `{}` itself", hook, target)
+                } else if let Some(path) = dependency_path(&target, name, &graph) {
+                    format!(
+                        "{} hook runs `{}`, which depends on `{}`: {}",
+                        hook,
+                        target,
+                        name,
+                        path.join(" -> ")
+                    )
+                } else {
+                    continue;
+                };
+                self.push(&Scope::Task(name.to_string()), message);
+            }
+        }
+    }
+}
+
+/// The shortest chain of dependencies leading from `from` to `to`, both included.
+fn dependency_path<'a>(
+    from: &'a str,
+    to: &str,
+    graph: &HashMap<&'a str, Vec<&'a str>>,
+) -> Option<Vec<&'a str>> {
+    let mut previous: HashMap<&str, &str> = HashMap::new();
+    let mut queue = VecDeque::from([from]);
+    while let Some(task) = queue.pop_front() {
+        if task == to {
+            let mut path = vec![task];
+            while let Some(&before) = previous.get(path[path.len() - 1]) {
+                path.push(before);
+            }
+            path.reverse();
+            return Some(path);
+        }
+        for &dependency in graph.get(task).into_iter().flatten() {
+            if dependency != from && !previous.contains_key(dependency) {
+                previous.insert(dependency, task);
+                queue.push_back(dependency);
+            }
+        }
+    }
+    None
+}
+
+fn find_cycles<'a>(
+    task: &'a str,
+    graph: &HashMap<&'a str, Vec<&'a str>>,
+    path: &mut Vec<&'a str>,
+    done: &mut HashSet<&'a str>,
+    cycles: &mut Vec<Vec<&'a str>>,
+) {
+    if let Some(start) = path.iter().position(|t| *t == task) {
+        let mut cycle = path[start..].to_vec();
+        cycle.push(task);
+        cycles.push(cycle);
+        return;
+    }
+    if done.contains(task) {
+        return;
+    }
+
+    path.push(task);
+    for dependency in &graph[task] {
+        find_cycles(dependency, graph, path, done, cycles);
+    }
+    path.pop();
+    done.insert(task);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn workflow(source: &str) -> WorkFlow {
+        let header = format!(
+            "name = \"test\"\nversion = \"{}\"\n",
+            env!("CARGO_PKG_VERSION")
+        );
+        toml::from_str(&(header + source)).unwrap()
+    }
+
+    #[test]
+    fn test_valid_workflow() {
+        let workflow = workflow(
+            r#"
+            [[templates]]
+            name = "echo"
+            command = "echo {{MESSAGE}}"
+
+            [tasks.task1]
+            command = "echo Hello"
+
+            [tasks.task2]
+            template = "echo"
+            dependencies = [{"task1" = "success"}]
+            on_error = ["task1", "template:echo"]
+            "#,
+        );
+        assert_eq!(validate(&workflow), vec![]);
+    }
+
+    #[test]
+    fn test_undefined_references() {
+        let workflow = workflow(
+            r#"
+            on_finish = ["plugin:logger"]
+
+            [tasks.task1]
+            template = "missing"
+            dependencies = ["nope", {"task2" = "done"}]
+            on_error = ["other:thing"]
+
+            [tasks.task2]
+            plugin = "notify"
+            "#,
+        );
+        let messages: Vec<String> = validate(&workflow).iter().map(|i| i.to_string()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "workflow: uses plugin `logger` which is not declared in [[plugins]]",
+                "task `task1`: uses undefined template `missing`",
+                "task `task1`: dependencies references undefined task `nope`",
+                "task `task1`: dependency on `task2` requires unknown status `done` (expected one of: success, up_to_date, failure, fail, timeout, skipped, condition_false, not_needed, any)",
+                "task `task1`: on_error entry `other:thing` has unknown type `other` (expected task, template or plugin)",
+                "task `task2`: uses plugin `notify` which is not declared in [[plugins]]",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_task_without_action() {
+        let workflow = workflow("[tasks.task1]\nrun = false\n");
+        assert_eq!(
+            validate(&workflow),
+            vec![Issue {
+                scope: Scope::Task("task1".to_string()),
+                message: "has no command, exec, template or plugin".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_programs() {
+        let workflow = workflow(
+            r#"
+            [tasks.task1]
+            command = "echo 1"
+            exec = ["echo", "1"]
+
+            [tasks.task2]
+            exec = []
+            shell = "bash"
+
+            [tasks.task3]
+            command = "print(3)"
+            shell = []
+
+            [[templates]]
+            name = "python"
+            command = "print(4)"
+            shell = "python3"
+            cwd = "scripts"
+            "#,
+        );
+        let messages: Vec<String> = validate(&workflow).iter().map(|i| i.to_string()).collect();
+        assert_eq!(
+            messages,
+            [
+                "task `task1`: has both command and exec",
+                "task `task2`: exec must not be empty",
+                "task `task2`: shell has no effect with exec",
+                "task `task3`: shell must not be empty",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_params() {
+        let workflow = workflow(
+            r#"
+            [params]
+            env = { type = "enum", values = ["staging", "prod"], default = "dev" }
+            region = { type = "enum" }
+            replicas = { type = "int", default = "two" }
+            verbose = { type = "bool", values = ["yes"], default = [true] }
+
+            [tasks]
+            "#,
+        );
+        let messages: Vec<String> = validate(&workflow).iter().map(|i| i.to_string()).collect();
+        assert_eq!(
+            messages,
+            [
+                "param `env`: default: `dev` is not one of: staging, prod",
+                "param `region`: enum has no values",
+                "param `replicas`: default: `two` is not an integer",
+                "param `verbose`: values are only allowed for enums",
+                "param `verbose`: default: must be a string, integer or boolean",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cycle() {
+        let workflow = workflow(
+            r#"
+            [tasks.task1]
+            command = "echo 1"
+            dependencies = ["task3"]
+
+            [tasks.task2]
+            command = "echo 2"
+            dependencies = ["task1"]
+
+            [tasks.task3]
+            command = "echo 3"
+            dependencies = ["task2"]
+            "#,
+        );
+        let messages: Vec<String> = validate(&workflow).iter().map(|i| i.to_string()).collect();
+        assert_eq!(
+            messages,
+            vec!["task `task1`: dependency cycle: task1 -> task3 -> task2 -> task1"]
+        );
+    }
+
+    #[test]
+    fn test_cycle_through_template() {
+        let workflow = workflow(
+            r#"
+            [[templates]]
+            name = "deploy"
+            command = "deploy"
+            dependencies = ["smoke"]
+
+            [tasks.release]
+            template = "deploy"
+
+            [tasks.smoke]
+            command = "smoke-test"
+            dependencies = ["release"]
+            "#,
+        );
+        let messages: Vec<String> = validate(&workflow).iter().map(|i| i.to_string()).collect();
+        assert_eq!(
+            messages,
+            vec!["task `release`: dependency cycle: release -> smoke -> release"]
+        );
+    }
+
+    #[test]
+    fn test_hook_cycle() {
+        let workflow = workflow(
+            r#"
+            [tasks.build]
+            command = "make"
+            on_error = ["task:report"]
+
+            [tasks.report]
+            command = "report"
+            dependencies = [{ build = "failure" }]
+
+            [tasks.lint]
+            command = "lint"
+            on_start = ["task:lint"]
+            on_finish = ["task:notify"]
+
+            [tasks.notify]
+            command = "notify"
+            dependencies = ["test"]
+
+            [tasks.test]
+            command = "test"
+            dependencies = ["lint"]
+            "#,
+        );
+        let messages: Vec<String> = validate(&workflow).iter().map(|i| i.to_string()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "task `build`: on_error hook runs `report`, which depends on `build`: report -> build",
+                "task `lint`: on_start hook runs `lint` itself",
+                "task `lint`: on_finish hook runs `notify`, which depends on `lint`: notify -> test -> lint",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resources() {
+        let workflow = workflow(
+            r#"
+            max_parallel = 0
+            timeout = "soon"
+
+            [resources]
+            db = 1
+            gpu = 0
+
+            [tasks.task1]
+            command = "echo 1"
+            resources = ["db", "network"]
+            "#,
+        );
+        let messages: Vec<String> = validate(&workflow).iter().map(|i| i.to_string()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "workflow: timeout: invalid duration `soon` (expected something like `30s`, `10m` or `1h30m`)",
+                "workflow: max_parallel must be at least 1",
+                "workflow: resource pool `gpu` must have a capacity of at least 1",
+                "task `task1`: uses undefined resource pool `network`",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_retry() {
+        let workflow = workflow(
+            r#"
+            [tasks.task1]
+            command = "curl example.com"
+            retry = 3
+            retry_delay = "1 second"
+            retry_backoff = "exponential"
+            retry_stderr = "reset|refused"
+
+            [[templates]]
+            name = "fetch"
+            command = "curl example.com"
+            retry_stderr = "(unclosed"
+            "#,
+        );
+        let messages: Vec<String> = validate(&workflow).iter().map(|i| i.to_string()).collect();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(
+            messages[0],
+            "task `task1`: retry_delay: invalid duration `1 second` (expected something like `30s`, `10m` or `1h30m`)"
+        );
+        assert!(messages[1].starts_with("template `fetch`: retry_stderr: regex parse error"));
+    }
+
+    #[test]
+    fn test_outputs() {
+        let workflow = workflow(
+            r#"
+            [tasks.build]
+            command = "make"
+            outputs = { version = { regex = "version (.+" }, log = "stdout" }
+
+            [tasks.deploy]
+            command = "deploy {{tasks.build.outputs.version}} {{tasks.build.outputs.sha}}"
+            env = { TARGET = "{{tasks.configure.outputs.target}}" }
+            "#,
+        );
+        let messages: Vec<String> = validate(&workflow).iter().map(|i| i.to_string()).collect();
+        assert_eq!(messages.len(), 3);
+        assert!(messages[0].starts_with("task `build`: output `version`: regex parse error"));
+        assert_eq!(
+            messages[1..],
+            [
+                "task `deploy`: uses output `sha`, which task `build` doesn't declare",
+                "task `deploy`: uses output `target` of undefined task `configure`",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sources() {
+        let workflow = workflow(
+            r#"
+            [tasks.build]
+            command = "make"
+            sources = ["src/**/*.c", "src/[.h"]
+            artifacts = ["build/app"]
+
+            [tasks.package]
+            command = "tar czf app.tar.gz build"
+            artifacts = ["app.tar.gz"]
+            "#,
+        );
+        let messages: Vec<String> = validate(&workflow).iter().map(|i| i.to_string()).collect();
+        assert_eq!(messages.len(), 2);
+        assert!(messages[0].starts_with("task `build`: sources: invalid glob `src/[.h`"));
+        assert_eq!(
+            messages[1],
+            "task `package`: artifacts have no effect without sources"
+        );
+    }
+
+    #[test]
+    fn test_conditions() {
+        let workflow = workflow(
+            r#"
+            [tasks.test]
+            command = "cargo test"
+            when = "tasks.deploy.status == 'success'"
+
+            [tasks.deploy]
+            command = "deploy"
+            when = "tasks.test.status == 'success' && tasks.lint.status == 'success'"
+
+            [tasks.publish]
+            command = "publish"
+            when = "env.CI = 'true'"
+            "#,
+        );
+        let messages: Vec<String> = validate(&workflow).iter().map(|i| i.to_string()).collect();
+        assert_eq!(
+            messages,
+            [
+                "task `deploy`: when references undefined task `lint`",
+                "task `publish`: when: unexpected character `=`",
+                "task `deploy`: dependency cycle: deploy -> test -> deploy",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_version_mismatch() {
+        let workflow: WorkFlow =
+            toml::from_str("name = \"test\"\nversion = \"^99.0.0\"\n[tasks]\n").unwrap();
+        assert_eq!(validate(&workflow).len(), 1);
+    }
+}